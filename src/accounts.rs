@@ -0,0 +1,300 @@
+use crate::error::Error;
+use solana_program::{
+    account_info::{next_account_info, AccountInfo},
+    entrypoint::ProgramResult,
+    program_error::ProgramError,
+    pubkey::Pubkey,
+    system_program::ID as SYSTEM_PROGRAM_ID,
+};
+use std::slice::Iter;
+
+// mints of the legacy token program and of token-2022 can both be staked
+pub fn is_token_program(key: &Pubkey) -> bool {
+    *key == spl_token::ID || *key == spl_token_2022::ID
+}
+
+// fails early with a clear error instead of an opaque unpack failure
+pub fn check_token_account_key(token_account: &AccountInfo) -> ProgramResult {
+    if *token_account.key == SYSTEM_PROGRAM_ID || is_token_program(token_account.key) {
+        return Err(Error::ProgramAsTokenAccount.into());
+    }
+    Ok(())
+}
+
+pub struct CreateGameAccounts<'a, 'b> {
+    pub player: &'a AccountInfo<'b>,
+    pub game_account: &'a AccountInfo<'b>,
+    pub mint: &'a AccountInfo<'b>,
+    pub escrow: &'a AccountInfo<'b>,
+    pub token_account: &'a AccountInfo<'b>,
+    pub token_program: &'a AccountInfo<'b>,
+    pub system_program: &'a AccountInfo<'b>,
+    pub stats: &'a AccountInfo<'b>,
+}
+
+impl<'a, 'b> CreateGameAccounts<'a, 'b> {
+    pub fn from_account_iter(iter: &mut Iter<'a, AccountInfo<'b>>) -> Result<Self, ProgramError> {
+        let accounts = Self {
+            player: next_account_info(iter)?,
+            game_account: next_account_info(iter)?,
+            mint: next_account_info(iter)?,
+            escrow: next_account_info(iter)?,
+            token_account: next_account_info(iter)?,
+            token_program: next_account_info(iter)?,
+            system_program: next_account_info(iter)?,
+            stats: next_account_info(iter)?,
+        };
+        // the game account only signs when it isn't derived from a nonce
+        if !accounts.player.is_signer {
+            return Err(ProgramError::MissingRequiredSignature);
+        }
+        if *accounts.system_program.key != SYSTEM_PROGRAM_ID
+            || !is_token_program(accounts.token_program.key)
+        {
+            return Err(ProgramError::IncorrectProgramId);
+        }
+        check_token_account_key(accounts.token_account)?;
+        Ok(accounts)
+    }
+}
+
+pub struct AcceptGameAccounts<'a, 'b> {
+    pub player_two: &'a AccountInfo<'b>,
+    pub game_account: &'a AccountInfo<'b>,
+    pub escrow: &'a AccountInfo<'b>,
+    pub token_account: &'a AccountInfo<'b>,
+    pub token_program: &'a AccountInfo<'b>,
+}
+
+impl<'a, 'b> AcceptGameAccounts<'a, 'b> {
+    pub fn from_account_iter(iter: &mut Iter<'a, AccountInfo<'b>>) -> Result<Self, ProgramError> {
+        let accounts = Self {
+            player_two: next_account_info(iter)?,
+            game_account: next_account_info(iter)?,
+            escrow: next_account_info(iter)?,
+            token_account: next_account_info(iter)?,
+            token_program: next_account_info(iter)?,
+        };
+        if !accounts.player_two.is_signer {
+            return Err(ProgramError::MissingRequiredSignature);
+        }
+        if !is_token_program(accounts.token_program.key) {
+            return Err(ProgramError::IncorrectProgramId);
+        }
+        check_token_account_key(accounts.token_account)?;
+        Ok(accounts)
+    }
+}
+
+// used by PlayGame, ForceDraw, ClaimDraw, Resign and ClaimTimeout
+pub struct PlayerGameAccounts<'a, 'b> {
+    pub player: &'a AccountInfo<'b>,
+    pub game_account: &'a AccountInfo<'b>,
+}
+
+impl<'a, 'b> PlayerGameAccounts<'a, 'b> {
+    pub fn from_account_iter(iter: &mut Iter<'a, AccountInfo<'b>>) -> Result<Self, ProgramError> {
+        let accounts = Self {
+            player: next_account_info(iter)?,
+            game_account: next_account_info(iter)?,
+        };
+        if !accounts.player.is_signer {
+            return Err(ProgramError::MissingRequiredSignature);
+        }
+        Ok(accounts)
+    }
+}
+
+// the payout token accounts depend on the game's result and are read after these
+pub struct CloseGameAccounts<'a, 'b> {
+    pub player_one: &'a AccountInfo<'b>,
+    pub game_account: &'a AccountInfo<'b>,
+    pub escrow: &'a AccountInfo<'b>,
+    pub authority: &'a AccountInfo<'b>,
+    pub token_program: &'a AccountInfo<'b>,
+    pub system_program: &'a AccountInfo<'b>,
+    pub stats: &'a AccountInfo<'b>,
+}
+
+impl<'a, 'b> CloseGameAccounts<'a, 'b> {
+    pub fn from_account_iter(iter: &mut Iter<'a, AccountInfo<'b>>) -> Result<Self, ProgramError> {
+        let accounts = Self {
+            player_one: next_account_info(iter)?,
+            game_account: next_account_info(iter)?,
+            escrow: next_account_info(iter)?,
+            authority: next_account_info(iter)?,
+            token_program: next_account_info(iter)?,
+            system_program: next_account_info(iter)?,
+            stats: next_account_info(iter)?,
+        };
+        if !is_token_program(accounts.token_program.key)
+            || *accounts.system_program.key != SYSTEM_PROGRAM_ID
+        {
+            return Err(ProgramError::IncorrectProgramId);
+        }
+        Ok(accounts)
+    }
+}
+
+pub struct CancelGameAccounts<'a, 'b> {
+    pub player_one: &'a AccountInfo<'b>,
+    pub game_account: &'a AccountInfo<'b>,
+    pub escrow: &'a AccountInfo<'b>,
+    pub token_account: &'a AccountInfo<'b>,
+    pub authority: &'a AccountInfo<'b>,
+    pub token_program: &'a AccountInfo<'b>,
+}
+
+impl<'a, 'b> CancelGameAccounts<'a, 'b> {
+    pub fn from_account_iter(iter: &mut Iter<'a, AccountInfo<'b>>) -> Result<Self, ProgramError> {
+        let accounts = Self {
+            player_one: next_account_info(iter)?,
+            game_account: next_account_info(iter)?,
+            escrow: next_account_info(iter)?,
+            token_account: next_account_info(iter)?,
+            authority: next_account_info(iter)?,
+            token_program: next_account_info(iter)?,
+        };
+        if !is_token_program(accounts.token_program.key) {
+            return Err(ProgramError::IncorrectProgramId);
+        }
+        check_token_account_key(accounts.token_account)?;
+        Ok(accounts)
+    }
+}
+
+pub struct BurnTrophyAccounts<'a, 'b> {
+    pub player_one: &'a AccountInfo<'b>,
+    pub game_account: &'a AccountInfo<'b>,
+}
+
+impl<'a, 'b> BurnTrophyAccounts<'a, 'b> {
+    pub fn from_account_iter(iter: &mut Iter<'a, AccountInfo<'b>>) -> Result<Self, ProgramError> {
+        let accounts = Self {
+            player_one: next_account_info(iter)?,
+            game_account: next_account_info(iter)?,
+        };
+        if !accounts.player_one.is_signer {
+            return Err(ProgramError::MissingRequiredSignature);
+        }
+        Ok(accounts)
+    }
+}
+
+pub struct DoubleOrNothingAccounts<'a, 'b> {
+    pub player_one: &'a AccountInfo<'b>,
+    pub player_two: &'a AccountInfo<'b>,
+    pub game_account: &'a AccountInfo<'b>,
+    pub escrow: &'a AccountInfo<'b>,
+    pub token_account: &'a AccountInfo<'b>,
+    pub token_program: &'a AccountInfo<'b>,
+}
+
+impl<'a, 'b> DoubleOrNothingAccounts<'a, 'b> {
+    pub fn from_account_iter(iter: &mut Iter<'a, AccountInfo<'b>>) -> Result<Self, ProgramError> {
+        let accounts = Self {
+            player_one: next_account_info(iter)?,
+            player_two: next_account_info(iter)?,
+            game_account: next_account_info(iter)?,
+            escrow: next_account_info(iter)?,
+            token_account: next_account_info(iter)?,
+            token_program: next_account_info(iter)?,
+        };
+        if !accounts.player_one.is_signer || !accounts.player_two.is_signer {
+            return Err(ProgramError::MissingRequiredSignature);
+        }
+        if !is_token_program(accounts.token_program.key) {
+            return Err(ProgramError::IncorrectProgramId);
+        }
+        check_token_account_key(accounts.token_account)?;
+        Ok(accounts)
+    }
+}
+
+pub struct SlashBondAccounts<'a, 'b> {
+    pub winner: &'a AccountInfo<'b>,
+    pub game_account: &'a AccountInfo<'b>,
+    pub escrow: &'a AccountInfo<'b>,
+    pub authority: &'a AccountInfo<'b>,
+    pub token_account: &'a AccountInfo<'b>,
+    pub token_program: &'a AccountInfo<'b>,
+    pub stats: &'a AccountInfo<'b>,
+}
+
+impl<'a, 'b> SlashBondAccounts<'a, 'b> {
+    pub fn from_account_iter(iter: &mut Iter<'a, AccountInfo<'b>>) -> Result<Self, ProgramError> {
+        let accounts = Self {
+            winner: next_account_info(iter)?,
+            game_account: next_account_info(iter)?,
+            escrow: next_account_info(iter)?,
+            authority: next_account_info(iter)?,
+            token_account: next_account_info(iter)?,
+            token_program: next_account_info(iter)?,
+            stats: next_account_info(iter)?,
+        };
+        if !accounts.winner.is_signer {
+            return Err(ProgramError::MissingRequiredSignature);
+        }
+        if !is_token_program(accounts.token_program.key) {
+            return Err(ProgramError::IncorrectProgramId);
+        }
+        check_token_account_key(accounts.token_account)?;
+        Ok(accounts)
+    }
+}
+
+// the fee collector's token account follows when the previous game was won with a fee
+pub struct RematchAccounts<'a, 'b> {
+    pub player_one: &'a AccountInfo<'b>,
+    pub player_two: &'a AccountInfo<'b>,
+    pub game_account: &'a AccountInfo<'b>,
+    pub escrow: &'a AccountInfo<'b>,
+    pub authority: &'a AccountInfo<'b>,
+    pub token_account_one: &'a AccountInfo<'b>,
+    pub token_account_two: &'a AccountInfo<'b>,
+    pub token_program: &'a AccountInfo<'b>,
+}
+
+impl<'a, 'b> RematchAccounts<'a, 'b> {
+    pub fn from_account_iter(iter: &mut Iter<'a, AccountInfo<'b>>) -> Result<Self, ProgramError> {
+        let accounts = Self {
+            player_one: next_account_info(iter)?,
+            player_two: next_account_info(iter)?,
+            game_account: next_account_info(iter)?,
+            escrow: next_account_info(iter)?,
+            authority: next_account_info(iter)?,
+            token_account_one: next_account_info(iter)?,
+            token_account_two: next_account_info(iter)?,
+            token_program: next_account_info(iter)?,
+        };
+        if !accounts.player_one.is_signer || !accounts.player_two.is_signer {
+            return Err(ProgramError::MissingRequiredSignature);
+        }
+        if !is_token_program(accounts.token_program.key) {
+            return Err(ProgramError::IncorrectProgramId);
+        }
+        check_token_account_key(accounts.token_account_one)?;
+        check_token_account_key(accounts.token_account_two)?;
+        Ok(accounts)
+    }
+}
+
+pub struct AgreeDrawAccounts<'a, 'b> {
+    pub player_one: &'a AccountInfo<'b>,
+    pub player_two: &'a AccountInfo<'b>,
+    pub game_account: &'a AccountInfo<'b>,
+}
+
+impl<'a, 'b> AgreeDrawAccounts<'a, 'b> {
+    pub fn from_account_iter(iter: &mut Iter<'a, AccountInfo<'b>>) -> Result<Self, ProgramError> {
+        let accounts = Self {
+            player_one: next_account_info(iter)?,
+            player_two: next_account_info(iter)?,
+            game_account: next_account_info(iter)?,
+        };
+        if !accounts.player_one.is_signer || !accounts.player_two.is_signer {
+            return Err(ProgramError::MissingRequiredSignature);
+        }
+        Ok(accounts)
+    }
+}
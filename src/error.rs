@@ -1,28 +1,74 @@
 use solana_program::program_error::ProgramError;
 use thiserror::Error;
 
+// the discriminants are the custom error codes clients decode, they never change and
+// new variants take the next free number
 #[derive(Error, Debug)]
 pub enum Error {
     #[error("You can not accept the provided game")]
-    UnauthorizedToAccept,
+    UnauthorizedToAccept = 0,
     #[error("Can not accept a game twice")]
-    AlreadyAccepted,
+    AlreadyAccepted = 1,
     #[error("Game has not been accepted yet")]
-    UnacceptedGame,
+    UnacceptedGame = 2,
     #[error("Game is already over")]
-    GameAlreayOver,
+    GameAlreadyOver = 3,
     #[error("The tile position specified is invalid")]
-    InvalidTileSelected,
+    InvalidTileSelected = 4,
     #[error("Selected tile is already occupied")]
-    TileOccupied,
-    #[error("Either the player not part of the game, or it is not player's turn")]
-    CanNotPlay,
+    TileOccupied = 5,
+    #[error("The player is not part of the game")]
+    CanNotPlay = 6,
     #[error("Can not close an ongoing game")]
-    OngoingGame,
+    OngoingGame = 7,
     #[error("Game has already been accepted and can't be closed")]
-    UnclosableGame,
+    UnclosableGame = 8,
     #[error("You can not close the provided game")]
-    UnauthorizedToClose,
+    UnauthorizedToClose = 9,
+    #[error("The join secret does not match the open challenge")]
+    InvalidJoinSecret = 10,
+    #[error("Arithmetic overflow")]
+    Overflow = 11,
+    #[error("The challenge has expired")]
+    ChallengeExpired = 12,
+    #[error("The challenge has not expired yet")]
+    ChallengeNotExpired = 13,
+    #[error("Game has already been settled")]
+    AlreadySettled = 14,
+    #[error("Only settled games kept as trophies can be burned")]
+    NotATrophy = 15,
+    #[error("Game has not exceeded its maximum duration")]
+    DurationNotExceeded = 16,
+    #[error("Game was created by an incompatible program version")]
+    UnsupportedGameVersion = 17,
+    #[error("Early draws are not allowed for this game")]
+    EarlyDrawNotAllowed = 18,
+    #[error("Game can still be won")]
+    NotDrawn = 19,
+    #[error("A program account was passed in place of a token account")]
+    ProgramAsTokenAccount = 20,
+    #[error("Game label is not valid UTF-8")]
+    InvalidLabel = 21,
+    #[error("Only a won game can be continued as double or nothing")]
+    NotWon = 22,
+    #[error("Game is not secured by a bond")]
+    NotBonded = 23,
+    #[error("It is not the player's turn")]
+    NotYourTurn = 24,
+    #[error("The opponent has not run out of time yet")]
+    TimeoutNotReached = 25,
+    #[error("Fee can not exceed 10000 basis points")]
+    InvalidFee = 26,
+    #[error("Board size or win length out of range")]
+    InvalidBoardSize = 27,
+    #[error("The escrow still holds tokens")]
+    EscrowNotEmpty = 28,
+    #[error("Game was cancelled before it was accepted")]
+    GameCancelled = 29,
+    #[error("The loser can still settle the game")]
+    SettlementWindowOpen = 30,
+    #[error("The game's fee is not the one the accepter agreed to")]
+    FeeMismatch = 31,
 }
 
 impl From<Error> for ProgramError {
@@ -30,3 +76,56 @@ impl From<Error> for ProgramError {
         Self::Custom(e as u32)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn game_already_over_keeps_the_code_of_the_misspelled_variant() {
+        assert_eq!(
+            ProgramError::from(Error::GameAlreadyOver),
+            ProgramError::Custom(3)
+        );
+    }
+
+    #[test]
+    fn every_variant_maps_to_its_pinned_code() {
+        for (error, code) in [
+            (Error::UnauthorizedToAccept, 0),
+            (Error::AlreadyAccepted, 1),
+            (Error::UnacceptedGame, 2),
+            (Error::GameAlreadyOver, 3),
+            (Error::InvalidTileSelected, 4),
+            (Error::TileOccupied, 5),
+            (Error::CanNotPlay, 6),
+            (Error::OngoingGame, 7),
+            (Error::UnclosableGame, 8),
+            (Error::UnauthorizedToClose, 9),
+            (Error::InvalidJoinSecret, 10),
+            (Error::Overflow, 11),
+            (Error::ChallengeExpired, 12),
+            (Error::ChallengeNotExpired, 13),
+            (Error::AlreadySettled, 14),
+            (Error::NotATrophy, 15),
+            (Error::DurationNotExceeded, 16),
+            (Error::UnsupportedGameVersion, 17),
+            (Error::EarlyDrawNotAllowed, 18),
+            (Error::NotDrawn, 19),
+            (Error::ProgramAsTokenAccount, 20),
+            (Error::InvalidLabel, 21),
+            (Error::NotWon, 22),
+            (Error::NotBonded, 23),
+            (Error::NotYourTurn, 24),
+            (Error::TimeoutNotReached, 25),
+            (Error::InvalidFee, 26),
+            (Error::InvalidBoardSize, 27),
+            (Error::EscrowNotEmpty, 28),
+            (Error::GameCancelled, 29),
+            (Error::SettlementWindowOpen, 30),
+            (Error::FeeMismatch, 31),
+        ] {
+            assert_eq!(ProgramError::from(error), ProgramError::Custom(code));
+        }
+    }
+}
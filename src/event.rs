@@ -0,0 +1,275 @@
+use crate::state::{GameSnapshot, Move, Symbol};
+use borsh::{BorshDeserialize, BorshSerialize};
+use solana_program::{
+    entrypoint::ProgramResult, hash::Hash, msg, program::set_return_data, pubkey::Pubkey,
+};
+
+// every event is also logged as one line: the prefix, then space separated key=value pairs
+// starting with kind and game. keys are never renamed, new ones are only appended
+pub const LOG_PREFIX: &str = "TTT-EVENT:";
+
+// what a state changing instruction did to a game, set as the return data so that
+// indexers decode a single format
+#[derive(Debug, PartialEq, BorshSerialize, BorshDeserialize)]
+pub enum GameEvent {
+    Created {
+        game_id: [u8; 32],
+        players: [Pubkey; 2],
+        stake_mint: Pubkey,
+        stake_amount: u64,
+    },
+    Accepted(GameSnapshot),
+    Moved {
+        game_id: [u8; 32],
+        player: Pubkey,
+        play: Move,
+        symbol: Symbol,
+        turns: u8,
+    },
+    // the move, resignation or timeout that ended the game
+    Won {
+        game_id: [u8; 32],
+        winner: Pubkey,
+    },
+    Drawn {
+        game_id: [u8; 32],
+    },
+    Cancelled {
+        game_id: [u8; 32],
+    },
+    // the stakes were paid out, winner is none for a draw
+    Settled {
+        game_id: [u8; 32],
+        winner: Option<Pubkey>,
+    },
+    // a game of a series ended and the next one started, winner is none for a draw
+    RoundEnded {
+        game_id: [u8; 32],
+        winner: Option<Pubkey>,
+        wins: [u8; 2],
+    },
+    // the loser matched the doubled winnings and a new game started
+    Restaked {
+        game_id: [u8; 32],
+        stake_amount: u64,
+    },
+    // the winner took both bonds, amount is the total paid out
+    Slashed {
+        game_id: [u8; 32],
+        winner: Pubkey,
+        amount: u64,
+    },
+    // the trophy account was closed and its rent reclaimed
+    Burned {
+        game_id: [u8; 32],
+    },
+}
+
+impl GameEvent {
+    pub fn emit(&self) -> ProgramResult {
+        self.log();
+        set_return_data(&self.try_to_vec()?);
+        Ok(())
+    }
+
+    fn log(&self) {
+        match self {
+            Self::Created {
+                game_id,
+                players,
+                stake_mint,
+                stake_amount,
+            } => msg!(
+                "{} kind=created game={} player_one={} player_two={} mint={} amount={}",
+                LOG_PREFIX,
+                Hash::new_from_array(*game_id),
+                players[0],
+                players[1],
+                stake_mint,
+                stake_amount
+            ),
+            Self::Accepted(snapshot) => msg!(
+                "{} kind=accepted game={} player_one={} player_two={} first={}",
+                LOG_PREFIX,
+                Hash::new_from_array(snapshot.game_id),
+                snapshot.players[0],
+                snapshot.players[1],
+                snapshot.next_player
+            ),
+            Self::Moved {
+                game_id,
+                player,
+                play,
+                symbol,
+                turns,
+            } => msg!(
+                "{} kind=moved game={} player={} row={} col={} symbol={} turns={}",
+                LOG_PREFIX,
+                Hash::new_from_array(*game_id),
+                player,
+                play.row,
+                play.col,
+                match symbol {
+                    Symbol::X => "X",
+                    Symbol::O => "O",
+                },
+                turns
+            ),
+            Self::Won { game_id, winner } => msg!(
+                "{} kind=won game={} winner={}",
+                LOG_PREFIX,
+                Hash::new_from_array(*game_id),
+                winner
+            ),
+            Self::Drawn { game_id } => msg!(
+                "{} kind=drawn game={}",
+                LOG_PREFIX,
+                Hash::new_from_array(*game_id)
+            ),
+            Self::Cancelled { game_id } => msg!(
+                "{} kind=cancelled game={}",
+                LOG_PREFIX,
+                Hash::new_from_array(*game_id)
+            ),
+            Self::Settled { game_id, winner } => match winner {
+                Some(winner) => msg!(
+                    "{} kind=settled game={} winner={}",
+                    LOG_PREFIX,
+                    Hash::new_from_array(*game_id),
+                    winner
+                ),
+                None => msg!(
+                    "{} kind=settled game={} winner=none",
+                    LOG_PREFIX,
+                    Hash::new_from_array(*game_id)
+                ),
+            },
+            Self::RoundEnded {
+                game_id,
+                winner,
+                wins,
+            } => match winner {
+                Some(winner) => msg!(
+                    "{} kind=round game={} winner={} wins_one={} wins_two={}",
+                    LOG_PREFIX,
+                    Hash::new_from_array(*game_id),
+                    winner,
+                    wins[0],
+                    wins[1]
+                ),
+                None => msg!(
+                    "{} kind=round game={} winner=none wins_one={} wins_two={}",
+                    LOG_PREFIX,
+                    Hash::new_from_array(*game_id),
+                    wins[0],
+                    wins[1]
+                ),
+            },
+            Self::Restaked {
+                game_id,
+                stake_amount,
+            } => msg!(
+                "{} kind=restaked game={} amount={}",
+                LOG_PREFIX,
+                Hash::new_from_array(*game_id),
+                stake_amount
+            ),
+            Self::Slashed {
+                game_id,
+                winner,
+                amount,
+            } => msg!(
+                "{} kind=slashed game={} winner={} amount={}",
+                LOG_PREFIX,
+                Hash::new_from_array(*game_id),
+                winner,
+                amount
+            ),
+            Self::Burned { game_id } => msg!(
+                "{} kind=burned game={}",
+                LOG_PREFIX,
+                Hash::new_from_array(*game_id)
+            ),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::processor::tests::{install_stubs, return_data, take_logs};
+    use crate::state::tests::setup;
+
+    #[test]
+    fn every_event_decodes_from_the_return_data() {
+        install_stubs();
+        let game = setup();
+        let game_id = game.game_id;
+        let winner = game.players[0];
+        let events = [
+            GameEvent::Created {
+                game_id,
+                players: game.players,
+                stake_mint: game.stake_mint,
+                stake_amount: game.stake_amount,
+            },
+            GameEvent::Accepted(game.snapshot()),
+            GameEvent::Moved {
+                game_id,
+                player: winner,
+                play: Move { row: 1, col: 2 },
+                symbol: Symbol::X,
+                turns: 1,
+            },
+            GameEvent::Won { game_id, winner },
+            GameEvent::Drawn { game_id },
+            GameEvent::Cancelled { game_id },
+            GameEvent::Settled {
+                game_id,
+                winner: None,
+            },
+            GameEvent::RoundEnded {
+                game_id,
+                winner: Some(winner),
+                wins: [1, 0],
+            },
+            GameEvent::Restaked {
+                game_id,
+                stake_amount: 200,
+            },
+            GameEvent::Slashed {
+                game_id,
+                winner,
+                amount: 20,
+            },
+            GameEvent::Burned { game_id },
+        ];
+        for event in events {
+            event.emit().unwrap();
+            assert_eq!(GameEvent::try_from_slice(&return_data()).unwrap(), event);
+        }
+    }
+
+    #[test]
+    fn every_event_logs_one_prefixed_line() {
+        install_stubs();
+        let game = setup();
+        let game_id = game.game_id;
+        let winner = game.players[0];
+        take_logs();
+        GameEvent::Won { game_id, winner }.emit().unwrap();
+        GameEvent::Settled {
+            game_id,
+            winner: None,
+        }
+        .emit()
+        .unwrap();
+        assert_eq!(
+            take_logs(),
+            vec![
+                format!("TTT-EVENT: kind=won game={} winner={}", game.id(), winner),
+                format!("TTT-EVENT: kind=settled game={} winner=none", game.id()),
+            ]
+        );
+    }
+}
@@ -1,85 +1,1131 @@
-use borsh::BorshDeserialize;
+use crate::processor::{find_escrow_address, find_native_escrow_address};
+use crate::state::{Move, Stake, MAX_BOARD_SIZE};
+use borsh::{BorshDeserialize, BorshSerialize};
+use solana_program::instruction::AccountMeta;
 use solana_program::program_error::ProgramError;
 use solana_program::pubkey::Pubkey;
+use solana_program::system_program::ID as SYSTEM_PROGRAM_ID;
 
+// longest sequence validate_sequence accepts. the program has no instruction that takes a
+// batch, the one place a batch exists is a client putting several instructions for the same
+// game into one transaction, which this keeps within compute and transaction size limits
+pub const MAX_BATCH: usize = 16;
+
+// shortest CreateGame payload: no fee collector, no extra stakes and no join secret
+const CREATE_GAME_MIN_LEN: usize = 32 + 8 + 8 + 8 + 2 + 8 * 3 + 3 + 3 + 1 + 1 + 32 + 1;
+
+#[derive(Debug, PartialEq)]
 pub enum Instruction {
     CreateGame {
         player_two: Pubkey,
+        // 0 on both sides makes a casual game, which takes the system program as mint
         stake_amount: u64,
+        // player two's stake, equal to stake_amount unless one side gives odds
+        stake_amount_two: u64,
+        // escrow only this much per player instead of the stake, 0 escrows the full stake
+        bond_amount: u64,
+        // cut of a won pot in basis points, the collector is only encoded when it's non zero
+        fee_bps: u16,
+        fee_collector: Pubkey,
+        // number of slots the challenge can be accepted for, 0 means it never expires
+        expiry_slots: u64,
+        // slots an accepted game can last before a draw can be forced, 0 means unlimited
+        max_duration_slots: u64,
+        // slots each move may take before the opponent can claim the game, 0 means unlimited
+        move_timeout_slots: u64,
+        // whether a provably drawn game can be settled before the board is full
+        allow_early_draw: bool,
+        // the player with the higher stake moves second to make up for it
+        higher_staker_moves_second: bool,
+        // completing a line loses the game instead of winning it
+        misere: bool,
+        // tiles per side, from 3 up to MAX_BOARD_SIZE
+        board_size: u8,
+        // symbols in a row needed to complete a line, from 3 up to board_size
+        win_len: u8,
+        // wins needed to take the match, the board resets between games. 0 or 1 is a single game
+        series_target: u8,
+        // keep the main stake in an escrow of the game's own, any yield it earns goes to the payout
+        dedicated_escrow: bool,
+        // derive the game account from ["game", player_one, player_two, nonce] instead of
+        // signing with a fresh keypair
+        game_nonce: Option<u64>,
+        // short title for spectators, utf-8 padded with zeros
+        label: [u8; 32],
+        // other mints wagered alongside the main stake, at most MAX_EXTRA_STAKES
+        extra_stakes: Vec<Stake>,
+        // only for open challenges (player_two == Pubkey::default()), none lets anyone
+        // take the seat
+        join_secret_hash: Option<[u8; 32]>,
     },
     /*
     player_one: signer, writable
     game: signer, writable,
-    mint
-    escrow // seeds = ["escrow", mint_pubkey]: writable
-    token_account: writable
+    mint (the system program for a game staking lamports)
+    escrow // seeds = ["escrow", mint_pubkey(, game_pubkey if dedicated_escrow)], ["sol_escrow", game_pubkey] when staking lamports: writable (never created for casual games)
+    token_account: writable (unused when staking lamports)
     token_program
     system_program
+    stats // seeds = ["stats"]: writable
+    for each extra stake:
+        mint
+        escrow // seeds = ["escrow", mint_pubkey]: writable
+        token_account: writable
      */
-    AcceptGame,
+    AcceptGame {
+        // the fee the accepter agreed to, the game's has to match so that the creator can't
+        // route the pot to a collector of their own. fee_collector is only sent with a fee
+        fee_bps: u16,
+        fee_collector: Pubkey,
+        // preimage of the game's join_secret_hash, only for open challenges
+        join_secret: Option<[u8; 32]>,
+    },
     /*
-    player_two: signer
+    player_two: signer (writable when staking lamports)
     game: writable
-    escrow // seeds = ["escrow", mint_pubkey]: writable
-    token_account: writable
+    escrow // seeds = ["escrow", mint_pubkey], ["sol_escrow", game_pubkey] when staking lamports: writable
+    token_account: writable (unused when staking lamports)
     token_program
+    system_program (only when staking lamports)
+    for each extra stake:
+        escrow: writable
+        token_account: writable
      */
-    PlayGame {
-        row: usize,
-        col: usize,
-    },
+    PlayGame(Move),
     /*
     player: signer
     game: writable
      */
-    CloseGame,
+    CloseGame {
+        // leave the game account alive as a trophy instead of reclaiming its rent
+        keep_trophy: bool,
+        // create the winner's associated token account if it doesn't exist, paid by player_one
+        create_ata_if_missing: bool,
+        // participant the game account's rent goes to instead of player_one, not with keep_trophy
+        rent_recipient: Option<Pubkey>,
+    },
     /*
-    player_one
+    player_one: signer, writable
     game: writable
     escrow: writable
     authority
     token_program
     system_program
+    stats // seeds = ["stats"]: writable
+    (for an unaccepted game the game is cancelled instead, followed by CancelGame's
+    token_account and the accounts after its token_program)
+    (when staking lamports, only the wallets of the recipients follow: fee_collector, winner or player_one, player_two)
+    (nothing follows for casual games)
+    fee_token_account: writable (only for wins of a game with a fee, owned by fee_collector)
     winner_token_account/token_account_one: writable
     token_account_two: writable (might not be present)
+    mint, winner, associated_token_program (only if create_ata_if_missing and winner_token_account does not exist)
+    winner: signer (only if winner_token_account is not owned by the winner)
+    loser: signer, loser_stake_account: writable, winner_stake_account: writable (bonded wins only)
+    for each extra stake:
+        escrow: writable
+        winner_token_account/token_account_one: writable
+        mint, winner, associated_token_program (same as above)
+        token_account_two: writable (might not be present)
+    rent_recipient: signer, writable (only if rent_recipient is set)
      */
     CancelGame,
     /*
+    player_one: signer (writable, not a signer if player_two cancels an expired challenge)
+    game: writable
+    escrow: writable
+    token_account: writable (unused when staking lamports)
+    authority
+    token_program
+    player_two: signer (only if player_one is not a signer)
+    for each extra stake:
+        escrow: writable
+        token_account: writable
+     */
+    BurnTrophy,
+    /*
+    player_one: signer, writable
+    game: writable
+     */
+    ForceDraw,
+    /*
+    player: signer
+    game: writable
+     */
+    InspectEscrow {
+        mint: Pubkey,
+    },
+    /*
+    escrow // seeds = ["escrow", mint_pubkey]
+     */
+    ClaimDraw,
+    /*
+    player: signer
+    game: writable
+     */
+    GetLegalMoves,
+    /*
+    game
+     */
+    DoubleOrNothing,
+    /*
     player_one: signer
+    player_two: signer
+    game: writable
+    escrow // seeds = ["escrow", mint_pubkey]: writable
+    loser_token_account: writable
+    token_program
+    for each extra stake:
+        escrow: writable
+        loser_token_account: writable
+     */
+    SlashBond,
+    /*
+    winner: signer
     game: writable
     escrow: writable
-    token_account: writable
     authority
+    winner_token_account: writable
     token_program
+    stats // seeds = ["stats"]: writable
+     */
+    Resign,
+    /*
+    player: signer
+    game: writable
+     */
+    ClaimTimeout,
+    /*
+    player: signer
+    game: writable
+     */
+    VerifyWinningMove(Move),
+    /*
+    game
+     */
+    Rematch,
+    /*
+    player_one: signer
+    player_two: signer
+    game: writable
+    escrow // seeds = ["escrow", mint_pubkey]: writable
+    authority
+    token_account_one: writable
+    token_account_two: writable
+    token_program
+    fee_token_account: writable (only if the game was won with a fee, owned by fee_collector)
+     */
+    CloseEscrow {
+        mint: Pubkey,
+        // set for the dedicated escrow of a game
+        game: Option<Pubkey>,
+    },
+    /*
+    recipient: signer, writable
+    escrow // seeds = ["escrow", mint_pubkey(, game_pubkey)]: writable
+    authority
+    token_program
+     */
+    AgreeDraw,
+    /*
+    player_one: signer
+    player_two: signer
+    game: writable
+     */
+    LogBoard,
+    /*
+    game
      */
 }
 
+// a tile off every board is rejected here, one that's only off this game's board fails in play
+fn unpack_move(rest: &[u8]) -> Result<Move, ProgramError> {
+    let play = match rest {
+        [row, col] => Move {
+            row: *row,
+            col: *col,
+        },
+        _ => return Err(ProgramError::InvalidInstructionData),
+    };
+    if play.row as usize >= MAX_BOARD_SIZE || play.col as usize >= MAX_BOARD_SIZE {
+        return Err(ProgramError::InvalidInstructionData);
+    }
+    Ok(play)
+}
+
 impl Instruction {
     pub fn unpack_from_slice(data: &[u8]) -> Result<Self, ProgramError> {
-        let (&first, rest) = data.split_first().unwrap();
+        let (&first, rest) = data
+            .split_first()
+            .ok_or(ProgramError::InvalidInstructionData)?;
         let variant = match first {
             0 => {
-                let player_two = Pubkey::deserialize(&mut &rest[..32])?;
-                let stake_amount = u64::deserialize(&mut &rest[32..40])?;
+                if rest.len() < CREATE_GAME_MIN_LEN {
+                    return Err(ProgramError::InvalidInstructionData);
+                }
+                let data = &mut &rest[..];
+                let player_two = Pubkey::deserialize(data)?;
+                let stake_amount = u64::deserialize(data)?;
+                let stake_amount_two = u64::deserialize(data)?;
+                let bond_amount = u64::deserialize(data)?;
+                let fee_bps = u16::deserialize(data)?;
+                let fee_collector = if fee_bps != 0 {
+                    Pubkey::deserialize(data)?
+                } else {
+                    Pubkey::default()
+                };
+                let expiry_slots = u64::deserialize(data)?;
+                let max_duration_slots = u64::deserialize(data)?;
+                let move_timeout_slots = u64::deserialize(data)?;
+                let allow_early_draw = bool::deserialize(data)?;
+                let higher_staker_moves_second = bool::deserialize(data)?;
+                let misere = bool::deserialize(data)?;
+                let board_size = u8::deserialize(data)?;
+                let win_len = u8::deserialize(data)?;
+                let series_target = u8::deserialize(data)?;
+                let dedicated_escrow = bool::deserialize(data)?;
+                let game_nonce = Option::<u64>::deserialize(data)?;
+                let label = <[u8; 32]>::deserialize(data)?;
+                let extra_stake_count = u8::deserialize(data)?;
+                let extra_stakes = (0..extra_stake_count)
+                    .map(|_| Stake::deserialize(data))
+                    .collect::<Result<Vec<_>, _>>()?;
+                let join_secret_hash = if player_two == Pubkey::default() && !data.is_empty() {
+                    Some(<[u8; 32]>::deserialize(data)?)
+                } else {
+                    None
+                };
+                // the payload has to be exactly what its fields add up to
+                if !data.is_empty() {
+                    return Err(ProgramError::InvalidInstructionData);
+                }
                 Self::CreateGame {
                     player_two,
                     stake_amount,
+                    stake_amount_two,
+                    bond_amount,
+                    fee_bps,
+                    fee_collector,
+                    expiry_slots,
+                    max_duration_slots,
+                    move_timeout_slots,
+                    allow_early_draw,
+                    higher_staker_moves_second,
+                    misere,
+                    board_size,
+                    win_len,
+                    series_target,
+                    dedicated_escrow,
+                    game_nonce,
+                    label,
+                    extra_stakes,
+                    join_secret_hash,
                 }
             }
-            1 => Self::AcceptGame,
-            2 => {
-                if rest.len() != 2 {
+            1 => {
+                let data = &mut &rest[..];
+                let fee_bps = u16::deserialize(data)?;
+                let fee_collector = if fee_bps != 0 {
+                    Pubkey::deserialize(data)?
+                } else {
+                    Pubkey::default()
+                };
+                let join_secret = match data.len() {
+                    0 => None,
+                    32 => Some(<[u8; 32]>::deserialize(data)?),
+                    _ => return Err(ProgramError::InvalidInstructionData),
+                };
+                Self::AcceptGame {
+                    fee_bps,
+                    fee_collector,
+                    join_secret,
+                }
+            }
+            2 => Self::PlayGame(unpack_move(rest)?),
+            3 => {
+                if rest.len() > 2 && rest.len() != 2 + 32 {
+                    return Err(ProgramError::InvalidInstructionData);
+                }
+                let keep_trophy = rest.first().copied().unwrap_or(0) != 0;
+                let rent_recipient = if rest.len() > 2 {
+                    Some(Pubkey::deserialize(&mut &rest[2..])?)
+                } else {
+                    None
+                };
+                if keep_trophy && rent_recipient.is_some() {
                     return Err(ProgramError::InvalidInstructionData);
                 }
-                Self::PlayGame {
-                    row: rest[0] as usize,
-                    col: rest[1] as usize,
+                Self::CloseGame {
+                    keep_trophy,
+                    create_ata_if_missing: rest.get(1).copied().unwrap_or(0) != 0,
+                    rent_recipient,
                 }
             }
-            3 => Self::CloseGame,
             4 => Self::CancelGame,
+            5 => Self::BurnTrophy,
+            6 => Self::ForceDraw,
+            7 => {
+                if rest.len() != 32 {
+                    return Err(ProgramError::InvalidInstructionData);
+                }
+                Self::InspectEscrow {
+                    mint: Pubkey::deserialize(&mut &rest[..])?,
+                }
+            }
+            8 => Self::ClaimDraw,
+            9 => Self::GetLegalMoves,
+            10 => Self::DoubleOrNothing,
+            11 => Self::SlashBond,
+            12 => Self::Resign,
+            13 => Self::ClaimTimeout,
+            14 => Self::VerifyWinningMove(unpack_move(rest)?),
+            15 => Self::Rematch,
+            16 => {
+                if rest.len() != 32 && rest.len() != 64 {
+                    return Err(ProgramError::InvalidInstructionData);
+                }
+                let data = &mut &rest[..];
+                Self::CloseEscrow {
+                    mint: Pubkey::deserialize(data)?,
+                    game: if data.is_empty() {
+                        None
+                    } else {
+                        Some(Pubkey::deserialize(data)?)
+                    },
+                }
+            }
+            17 => Self::AgreeDraw,
+            18 => Self::LogBoard,
             _ => return Err(ProgramError::InvalidInstructionData),
         };
         Ok(variant)
     }
+
+    // the exact bytes unpack_from_slice reads back, tag first
+    pub fn pack(&self) -> Vec<u8> {
+        let mut data = Vec::new();
+        match self {
+            Self::CreateGame {
+                player_two,
+                stake_amount,
+                stake_amount_two,
+                bond_amount,
+                fee_bps,
+                fee_collector,
+                expiry_slots,
+                max_duration_slots,
+                move_timeout_slots,
+                allow_early_draw,
+                higher_staker_moves_second,
+                misere,
+                board_size,
+                win_len,
+                series_target,
+                dedicated_escrow,
+                game_nonce,
+                label,
+                extra_stakes,
+                join_secret_hash,
+            } => {
+                data.push(0);
+                player_two.serialize(&mut data).unwrap();
+                stake_amount.serialize(&mut data).unwrap();
+                stake_amount_two.serialize(&mut data).unwrap();
+                bond_amount.serialize(&mut data).unwrap();
+                fee_bps.serialize(&mut data).unwrap();
+                if *fee_bps != 0 {
+                    fee_collector.serialize(&mut data).unwrap();
+                }
+                expiry_slots.serialize(&mut data).unwrap();
+                max_duration_slots.serialize(&mut data).unwrap();
+                move_timeout_slots.serialize(&mut data).unwrap();
+                allow_early_draw.serialize(&mut data).unwrap();
+                higher_staker_moves_second.serialize(&mut data).unwrap();
+                misere.serialize(&mut data).unwrap();
+                board_size.serialize(&mut data).unwrap();
+                win_len.serialize(&mut data).unwrap();
+                series_target.serialize(&mut data).unwrap();
+                dedicated_escrow.serialize(&mut data).unwrap();
+                game_nonce.serialize(&mut data).unwrap();
+                label.serialize(&mut data).unwrap();
+                data.push(extra_stakes.len() as u8);
+                for stake in extra_stakes {
+                    stake.serialize(&mut data).unwrap();
+                }
+                if let Some(join_secret_hash) = join_secret_hash {
+                    join_secret_hash.serialize(&mut data).unwrap();
+                }
+            }
+            Self::AcceptGame {
+                fee_bps,
+                fee_collector,
+                join_secret,
+            } => {
+                data.push(1);
+                fee_bps.serialize(&mut data).unwrap();
+                if *fee_bps != 0 {
+                    fee_collector.serialize(&mut data).unwrap();
+                }
+                if let Some(join_secret) = join_secret {
+                    join_secret.serialize(&mut data).unwrap();
+                }
+            }
+            Self::PlayGame(play) => {
+                data.push(2);
+                play.serialize(&mut data).unwrap();
+            }
+            Self::CloseGame {
+                keep_trophy,
+                create_ata_if_missing,
+                rent_recipient,
+            } => {
+                data.push(3);
+                data.push(*keep_trophy as u8);
+                data.push(*create_ata_if_missing as u8);
+                if let Some(rent_recipient) = rent_recipient {
+                    rent_recipient.serialize(&mut data).unwrap();
+                }
+            }
+            Self::CancelGame => data.push(4),
+            Self::BurnTrophy => data.push(5),
+            Self::ForceDraw => data.push(6),
+            Self::InspectEscrow { mint } => {
+                data.push(7);
+                mint.serialize(&mut data).unwrap();
+            }
+            Self::ClaimDraw => data.push(8),
+            Self::GetLegalMoves => data.push(9),
+            Self::DoubleOrNothing => data.push(10),
+            Self::SlashBond => data.push(11),
+            Self::Resign => data.push(12),
+            Self::ClaimTimeout => data.push(13),
+            Self::VerifyWinningMove(play) => {
+                data.push(14);
+                play.serialize(&mut data).unwrap();
+            }
+            Self::Rematch => data.push(15),
+            Self::CloseEscrow { mint, game } => {
+                data.push(16);
+                mint.serialize(&mut data).unwrap();
+                if let Some(game) = game {
+                    game.serialize(&mut data).unwrap();
+                }
+            }
+            Self::AgreeDraw => data.push(17),
+            Self::LogBoard => data.push(18),
+        }
+        data
+    }
+}
+
+// keys the account lists are built from, the pdas are derived from program_id and mint
+pub struct InstructionKeys {
+    pub program_id: Pubkey,
+    pub player_one: Pubkey,
+    pub player_two: Pubkey,
+    // the player signing PlayGame, ForceDraw, ClaimDraw, Resign, ClaimTimeout or SlashBond,
+    // or the rent recipient of CloseEscrow
+    pub player: Pubkey,
+    pub game: Pubkey,
+    pub mint: Pubkey,
+    // token account of the signing player, or the (first) payout account on close
+    pub token_account: Pubkey,
+    // player two's payout account when closing a draw, default otherwise
+    pub token_account_two: Pubkey,
+    // whether the game keeps its main stake in an escrow of its own
+    pub dedicated_escrow: bool,
+    // the program owning mint, spl_token::ID or spl_token_2022::ID
+    pub token_program: Pubkey,
+}
+
+impl Instruction {
+    // the accounts each instruction expects, extra stake accounts and the winner signing
+    // for someone else's payout account are not included
+    pub fn account_metas(&self, keys: &InstructionKeys) -> Vec<AccountMeta> {
+        let escrow = |mint: &Pubkey| find_escrow_address(&keys.program_id, mint, None).0;
+        let native = keys.mint == SYSTEM_PROGRAM_ID;
+        let main_escrow = if native {
+            find_native_escrow_address(&keys.program_id, &keys.game).0
+        } else {
+            find_escrow_address(
+                &keys.program_id,
+                &keys.mint,
+                keys.dedicated_escrow.then_some(&keys.game),
+            )
+            .0
+        };
+        let authority = Pubkey::find_program_address(&["authority".as_bytes()], &keys.program_id).0;
+        let stats = Pubkey::find_program_address(&["stats".as_bytes()], &keys.program_id).0;
+        match self {
+            Self::CreateGame { game_nonce, .. } => vec![
+                AccountMeta::new(keys.player_one, true),
+                AccountMeta::new(keys.game, game_nonce.is_none()),
+                AccountMeta::new_readonly(keys.mint, false),
+                AccountMeta::new(main_escrow, false),
+                AccountMeta::new(keys.token_account, false),
+                AccountMeta::new_readonly(keys.token_program, false),
+                AccountMeta::new_readonly(SYSTEM_PROGRAM_ID, false),
+                AccountMeta::new(stats, false),
+            ],
+            Self::AcceptGame { .. } if native => vec![
+                AccountMeta::new(keys.player_two, true),
+                AccountMeta::new(keys.game, false),
+                AccountMeta::new(main_escrow, false),
+                AccountMeta::new(keys.token_account, false),
+                AccountMeta::new_readonly(keys.token_program, false),
+                AccountMeta::new_readonly(SYSTEM_PROGRAM_ID, false),
+            ],
+            Self::AcceptGame { .. } => vec![
+                AccountMeta::new_readonly(keys.player_two, true),
+                AccountMeta::new(keys.game, false),
+                AccountMeta::new(main_escrow, false),
+                AccountMeta::new(keys.token_account, false),
+                AccountMeta::new_readonly(keys.token_program, false),
+            ],
+            Self::PlayGame(_)
+            | Self::ForceDraw
+            | Self::ClaimDraw
+            | Self::Resign
+            | Self::ClaimTimeout => vec![
+                AccountMeta::new_readonly(keys.player, true),
+                AccountMeta::new(keys.game, false),
+            ],
+            Self::CloseGame { rent_recipient, .. } => {
+                let mut metas = vec![
+                    AccountMeta::new(keys.player_one, true),
+                    AccountMeta::new(keys.game, false),
+                    AccountMeta::new(main_escrow, false),
+                    AccountMeta::new_readonly(authority, false),
+                    AccountMeta::new_readonly(keys.token_program, false),
+                    AccountMeta::new_readonly(SYSTEM_PROGRAM_ID, false),
+                    AccountMeta::new(stats, false),
+                ];
+                // a payout account left at the default key is not passed
+                for token_account in [keys.token_account, keys.token_account_two] {
+                    if token_account != Pubkey::default() {
+                        metas.push(AccountMeta::new(token_account, false));
+                    }
+                }
+                if let Some(rent_recipient) = rent_recipient {
+                    metas.push(AccountMeta::new(*rent_recipient, true));
+                }
+                metas
+            }
+            Self::CancelGame => vec![
+                AccountMeta::new(keys.player_one, true),
+                AccountMeta::new(keys.game, false),
+                AccountMeta::new(main_escrow, false),
+                AccountMeta::new(keys.token_account, false),
+                AccountMeta::new_readonly(authority, false),
+                AccountMeta::new_readonly(keys.token_program, false),
+            ],
+            Self::BurnTrophy => vec![
+                AccountMeta::new(keys.player_one, true),
+                AccountMeta::new(keys.game, false),
+            ],
+            Self::InspectEscrow { mint } => vec![AccountMeta::new_readonly(escrow(mint), false)],
+            Self::GetLegalMoves | Self::VerifyWinningMove(_) | Self::LogBoard => {
+                vec![AccountMeta::new_readonly(keys.game, false)]
+            }
+            Self::SlashBond => vec![
+                AccountMeta::new_readonly(keys.player, true),
+                AccountMeta::new(keys.game, false),
+                AccountMeta::new(main_escrow, false),
+                AccountMeta::new_readonly(authority, false),
+                AccountMeta::new(keys.token_account, false),
+                AccountMeta::new_readonly(keys.token_program, false),
+                AccountMeta::new(stats, false),
+            ],
+            Self::DoubleOrNothing => vec![
+                AccountMeta::new_readonly(keys.player_one, true),
+                AccountMeta::new_readonly(keys.player_two, true),
+                AccountMeta::new(keys.game, false),
+                AccountMeta::new(main_escrow, false),
+                AccountMeta::new(keys.token_account, false),
+                AccountMeta::new_readonly(keys.token_program, false),
+            ],
+            Self::AgreeDraw => vec![
+                AccountMeta::new_readonly(keys.player_one, true),
+                AccountMeta::new_readonly(keys.player_two, true),
+                AccountMeta::new(keys.game, false),
+            ],
+            Self::CloseEscrow { mint, game } => vec![
+                AccountMeta::new(keys.player, true),
+                AccountMeta::new(
+                    find_escrow_address(&keys.program_id, mint, game.as_ref()).0,
+                    false,
+                ),
+                AccountMeta::new_readonly(authority, false),
+                AccountMeta::new_readonly(keys.token_program, false),
+            ],
+            Self::Rematch => vec![
+                AccountMeta::new_readonly(keys.player_one, true),
+                AccountMeta::new_readonly(keys.player_two, true),
+                AccountMeta::new(keys.game, false),
+                AccountMeta::new(main_escrow, false),
+                AccountMeta::new_readonly(authority, false),
+                AccountMeta::new(keys.token_account, false),
+                AccountMeta::new(keys.token_account_two, false),
+                AccountMeta::new_readonly(keys.token_program, false),
+            ],
+        }
+    }
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum Stage {
+    // the game was created outside of the sequence, its state is unknown
+    Unknown,
+    Created,
+    Accepted,
+    Settled,
+    Closed,
+}
+
+pub fn validate_sequence(instrs: &[Instruction]) -> Result<(), ProgramError> {
+    if instrs.len() > MAX_BATCH {
+        return Err(ProgramError::InvalidArgument);
+    }
+    let mut stage = Stage::Unknown;
+    for (i, instr) in instrs.iter().enumerate() {
+        let allowed = match instr {
+            Instruction::CreateGame { .. } => i == 0,
+            Instruction::AcceptGame { .. } => matches!(stage, Stage::Unknown | Stage::Created),
+            Instruction::PlayGame(_)
+            | Instruction::ForceDraw
+            | Instruction::ClaimDraw
+            | Instruction::Resign
+            | Instruction::ClaimTimeout
+            | Instruction::DoubleOrNothing
+            | Instruction::SlashBond
+            | Instruction::Rematch
+            | Instruction::AgreeDraw => matches!(stage, Stage::Unknown | Stage::Accepted),
+            // closing an unaccepted game cancels it
+            Instruction::CloseGame { .. } => {
+                matches!(stage, Stage::Unknown | Stage::Created | Stage::Accepted)
+            }
+            Instruction::CancelGame => matches!(stage, Stage::Unknown | Stage::Created),
+            Instruction::BurnTrophy => matches!(stage, Stage::Unknown | Stage::Settled),
+            Instruction::InspectEscrow { .. }
+            | Instruction::GetLegalMoves
+            | Instruction::VerifyWinningMove(_)
+            | Instruction::CloseEscrow { .. }
+            | Instruction::LogBoard => true,
+        };
+        if !allowed {
+            return Err(ProgramError::InvalidInstructionData);
+        }
+        stage = match instr {
+            Instruction::CreateGame { .. } => Stage::Created,
+            Instruction::CloseGame { .. } if matches!(stage, Stage::Created) => Stage::Closed,
+            Instruction::AcceptGame { .. }
+            | Instruction::PlayGame(_)
+            | Instruction::ForceDraw
+            | Instruction::ClaimDraw
+            | Instruction::Resign
+            | Instruction::ClaimTimeout
+            | Instruction::DoubleOrNothing
+            | Instruction::Rematch
+            | Instruction::AgreeDraw => Stage::Accepted,
+            Instruction::CloseGame {
+                keep_trophy: true, ..
+            }
+            | Instruction::SlashBond => Stage::Settled,
+            Instruction::CloseGame {
+                keep_trophy: false, ..
+            }
+            | Instruction::CancelGame
+            | Instruction::BurnTrophy => Stage::Closed,
+            Instruction::InspectEscrow { .. }
+            | Instruction::GetLegalMoves
+            | Instruction::VerifyWinningMove(_)
+            | Instruction::CloseEscrow { .. }
+            | Instruction::LogBoard => stage,
+        };
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use crate::accounts::{
+        AcceptGameAccounts, AgreeDrawAccounts, BurnTrophyAccounts, CancelGameAccounts,
+        CloseGameAccounts, CreateGameAccounts, DoubleOrNothingAccounts, PlayerGameAccounts,
+        RematchAccounts, SlashBondAccounts,
+    };
+    use solana_program::account_info::AccountInfo;
+    use solana_program::entrypoint::ProgramResult;
+
+    pub(crate) fn create_game() -> Instruction {
+        Instruction::CreateGame {
+            player_two: Pubkey::new_unique(),
+            stake_amount: 100,
+            stake_amount_two: 100,
+            bond_amount: 0,
+            fee_bps: 0,
+            fee_collector: Pubkey::default(),
+            expiry_slots: 0,
+            max_duration_slots: 0,
+            move_timeout_slots: 0,
+            allow_early_draw: false,
+            higher_staker_moves_second: false,
+            misere: false,
+            board_size: 3,
+            win_len: 3,
+            series_target: 0,
+            dedicated_escrow: false,
+            game_nonce: None,
+            label: [0; 32],
+            extra_stakes: Vec::new(),
+            join_secret_hash: None,
+        }
+    }
+
+    pub(crate) fn accept(fee_bps: u16, join_secret: Option<[u8; 32]>) -> Instruction {
+        Instruction::AcceptGame {
+            fee_bps,
+            fee_collector: Pubkey::default(),
+            join_secret,
+        }
+    }
+
+    fn keys() -> InstructionKeys {
+        InstructionKeys {
+            program_id: Pubkey::new_unique(),
+            player_one: Pubkey::new_unique(),
+            player_two: Pubkey::new_unique(),
+            player: Pubkey::new_unique(),
+            game: Pubkey::new_unique(),
+            mint: Pubkey::new_unique(),
+            token_account: Pubkey::new_unique(),
+            token_account_two: Pubkey::new_unique(),
+            dedicated_escrow: false,
+            token_program: spl_token::ID,
+        }
+    }
+
+    type Parser = fn(&[AccountInfo]) -> ProgramResult;
+
+    // hands the metas to a processor account parser as account infos with the same flags,
+    // optionally with every signature stripped
+    fn parse_metas(
+        metas: &[AccountMeta],
+        signed: bool,
+        parse: impl FnOnce(&[AccountInfo]) -> ProgramResult,
+    ) -> ProgramResult {
+        let owner = Pubkey::default();
+        let mut lamports = vec![0u64; metas.len()];
+        let mut data = vec![Vec::new(); metas.len()];
+        let infos: Vec<AccountInfo> = metas
+            .iter()
+            .zip(lamports.iter_mut())
+            .zip(data.iter_mut())
+            .map(|((meta, lamports), data)| {
+                AccountInfo::new(
+                    &meta.pubkey,
+                    meta.is_signer && signed,
+                    meta.is_writable,
+                    lamports,
+                    data,
+                    &owner,
+                    false,
+                    0,
+                )
+            })
+            .collect();
+        parse(&infos)
+    }
+
+    #[test]
+    fn pack_round_trips_through_unpack() {
+        let mut open_with_fee = create_game();
+        if let Instruction::CreateGame {
+            player_two,
+            fee_bps,
+            fee_collector,
+            game_nonce,
+            extra_stakes,
+            join_secret_hash,
+            ..
+        } = &mut open_with_fee
+        {
+            *player_two = Pubkey::default();
+            *fee_bps = 250;
+            *fee_collector = Pubkey::new_unique();
+            *game_nonce = Some(3);
+            extra_stakes.push(Stake {
+                mint: Pubkey::new_unique(),
+                amount: 5,
+            });
+            *join_secret_hash = Some([9; 32]);
+        }
+        let play = Move { row: 2, col: 1 };
+        let mut open = create_game();
+        if let Instruction::CreateGame { player_two, .. } = &mut open {
+            *player_two = Pubkey::default();
+        }
+        let instructions = [
+            create_game(),
+            open,
+            open_with_fee,
+            accept(0, None),
+            Instruction::AcceptGame {
+                fee_bps: 250,
+                fee_collector: Pubkey::new_unique(),
+                join_secret: Some([9; 32]),
+            },
+            Instruction::PlayGame(play),
+            Instruction::CloseGame {
+                keep_trophy: true,
+                create_ata_if_missing: false,
+                rent_recipient: None,
+            },
+            Instruction::CloseGame {
+                keep_trophy: false,
+                create_ata_if_missing: true,
+                rent_recipient: Some(Pubkey::new_unique()),
+            },
+            Instruction::CancelGame,
+            Instruction::BurnTrophy,
+            Instruction::ForceDraw,
+            Instruction::InspectEscrow {
+                mint: Pubkey::new_unique(),
+            },
+            Instruction::ClaimDraw,
+            Instruction::GetLegalMoves,
+            Instruction::DoubleOrNothing,
+            Instruction::SlashBond,
+            Instruction::Resign,
+            Instruction::ClaimTimeout,
+            Instruction::VerifyWinningMove(play),
+            Instruction::Rematch,
+            Instruction::CloseEscrow {
+                mint: Pubkey::new_unique(),
+                game: None,
+            },
+            Instruction::CloseEscrow {
+                mint: Pubkey::new_unique(),
+                game: Some(Pubkey::new_unique()),
+            },
+            Instruction::AgreeDraw,
+            Instruction::LogBoard,
+        ];
+        for instruction in instructions {
+            assert_eq!(
+                Instruction::unpack_from_slice(&instruction.pack()),
+                Ok(instruction)
+            );
+        }
+        assert_eq!(
+            Instruction::unpack_from_slice(&[19]),
+            Err(ProgramError::InvalidInstructionData)
+        );
+    }
+
+    #[test]
+    fn sequences_are_capped_at_max_batch() {
+        let batch = |len| {
+            (0..len)
+                .map(|_| Instruction::PlayGame(Move { row: 0, col: 0 }))
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(validate_sequence(&batch(MAX_BATCH)), Ok(()));
+        assert_eq!(
+            validate_sequence(&batch(MAX_BATCH + 1)),
+            Err(ProgramError::InvalidArgument)
+        );
+    }
+
+    #[test]
+    fn account_metas_carry_the_signatures_the_processor_checks() {
+        let keys = keys();
+        let cases: Vec<(Instruction, Parser)> = vec![
+            (create_game(), |a| {
+                CreateGameAccounts::from_account_iter(&mut a.iter()).map(|_| ())
+            }),
+            (accept(0, None), |a| {
+                AcceptGameAccounts::from_account_iter(&mut a.iter()).map(|_| ())
+            }),
+            (Instruction::PlayGame(Move { row: 0, col: 0 }), |a| {
+                PlayerGameAccounts::from_account_iter(&mut a.iter()).map(|_| ())
+            }),
+            (Instruction::ForceDraw, |a| {
+                PlayerGameAccounts::from_account_iter(&mut a.iter()).map(|_| ())
+            }),
+            (Instruction::BurnTrophy, |a| {
+                BurnTrophyAccounts::from_account_iter(&mut a.iter()).map(|_| ())
+            }),
+            (Instruction::DoubleOrNothing, |a| {
+                DoubleOrNothingAccounts::from_account_iter(&mut a.iter()).map(|_| ())
+            }),
+            (Instruction::SlashBond, |a| {
+                SlashBondAccounts::from_account_iter(&mut a.iter()).map(|_| ())
+            }),
+            (Instruction::Rematch, |a| {
+                RematchAccounts::from_account_iter(&mut a.iter()).map(|_| ())
+            }),
+            (Instruction::AgreeDraw, |a| {
+                AgreeDrawAccounts::from_account_iter(&mut a.iter()).map(|_| ())
+            }),
+        ];
+        for (instruction, parse) in cases {
+            let metas = instruction.account_metas(&keys);
+            assert_eq!(parse_metas(&metas, true, parse), Ok(()));
+            assert_eq!(
+                parse_metas(&metas, false, parse),
+                Err(ProgramError::MissingRequiredSignature)
+            );
+        }
+        // closing and cancelling check the signer in the processor, the parsers only need
+        // the accounts to be there
+        let close = Instruction::CloseGame {
+            keep_trophy: false,
+            create_ata_if_missing: false,
+            rent_recipient: None,
+        };
+        let metas = close.account_metas(&keys);
+        assert!(metas[0].is_signer);
+        let parse =
+            |a: &[AccountInfo]| CloseGameAccounts::from_account_iter(&mut a.iter()).map(|_| ());
+        assert_eq!(parse_metas(&metas, true, parse), Ok(()));
+        let metas = Instruction::CancelGame.account_metas(&keys);
+        assert!(metas[0].is_signer);
+        let parse =
+            |a: &[AccountInfo]| CancelGameAccounts::from_account_iter(&mut a.iter()).map(|_| ());
+        assert_eq!(parse_metas(&metas, true, parse), Ok(()));
+    }
+
+    #[test]
+    fn account_metas_lock_the_game_only_when_it_changes() {
+        let keys = keys();
+        let game_meta = |instruction: Instruction| {
+            instruction
+                .account_metas(&keys)
+                .into_iter()
+                .find(|meta| meta.pubkey == keys.game)
+                .unwrap()
+        };
+        for instruction in [
+            Instruction::PlayGame(Move { row: 0, col: 0 }),
+            Instruction::ClaimDraw,
+            Instruction::Resign,
+            Instruction::ClaimTimeout,
+            Instruction::CancelGame,
+            Instruction::Rematch,
+        ] {
+            assert!(game_meta(instruction).is_writable);
+        }
+        for instruction in [
+            Instruction::GetLegalMoves,
+            Instruction::LogBoard,
+            Instruction::VerifyWinningMove(Move { row: 0, col: 0 }),
+        ] {
+            assert!(!game_meta(instruction).is_writable);
+        }
+        // a keypair game account signs its creation, a derived one can't
+        assert!(create_game().account_metas(&keys)[1].is_signer);
+        let mut derived = create_game();
+        if let Instruction::CreateGame { game_nonce, .. } = &mut derived {
+            *game_nonce = Some(7);
+        }
+        assert!(!derived.account_metas(&keys)[1].is_signer);
+    }
+
+    #[test]
+    fn moves_past_the_largest_board_are_rejected() {
+        for data in [[2, 5, 0], [2, 0, 5], [14, 5, 5]] {
+            assert_eq!(
+                Instruction::unpack_from_slice(&data),
+                Err(ProgramError::InvalidInstructionData)
+            );
+        }
+        assert_eq!(
+            Instruction::unpack_from_slice(&[2, 4, 4]),
+            Ok(Instruction::PlayGame(Move { row: 4, col: 4 }))
+        );
+    }
+
+    #[test]
+    fn empty_data_is_invalid() {
+        assert_eq!(
+            Instruction::unpack_from_slice(&[]),
+            Err(ProgramError::InvalidInstructionData)
+        );
+    }
+
+    #[test]
+    fn truncated_create_game_is_an_error() {
+        let data = create_game().pack();
+        assert_eq!(data.len(), 1 + CREATE_GAME_MIN_LEN);
+        for len in 1..data.len() {
+            assert_eq!(
+                Instruction::unpack_from_slice(&data[..len]),
+                Err(ProgramError::InvalidInstructionData)
+            );
+        }
+        let mut padded = data.clone();
+        padded.push(0);
+        assert_eq!(
+            Instruction::unpack_from_slice(&padded),
+            Err(ProgramError::InvalidInstructionData)
+        );
+    }
+
+    #[test]
+    fn sequences_follow_the_game_lifecycle() {
+        let play = || Instruction::PlayGame(Move { row: 0, col: 0 });
+        let close = |keep_trophy| Instruction::CloseGame {
+            keep_trophy,
+            create_ata_if_missing: false,
+            rent_recipient: None,
+        };
+        let valid = [
+            vec![
+                create_game(),
+                accept(0, None),
+                play(),
+                close(true),
+                Instruction::BurnTrophy,
+            ],
+            vec![create_game(), Instruction::CancelGame],
+            // the game was created earlier, so anything can come first
+            vec![play(), Instruction::Resign, close(false)],
+        ];
+        for sequence in valid {
+            assert_eq!(validate_sequence(&sequence), Ok(()));
+        }
+        let invalid = [
+            vec![accept(0, None), create_game()],
+            vec![create_game(), play()],
+            vec![create_game(), accept(0, None), Instruction::CancelGame],
+            vec![create_game(), accept(0, None), close(false), play()],
+            vec![
+                create_game(),
+                accept(0, None),
+                close(false),
+                Instruction::BurnTrophy,
+            ],
+        ];
+        for sequence in invalid {
+            assert_eq!(
+                validate_sequence(&sequence),
+                Err(ProgramError::InvalidInstructionData)
+            );
+        }
+    }
+
+    #[test]
+    fn a_move_is_exactly_one_byte_each_for_row_and_col() {
+        for data in [&[2][..], &[2, 1], &[2, 1, 1, 0], &[14, 1]] {
+            assert_eq!(
+                Instruction::unpack_from_slice(data),
+                Err(ProgramError::InvalidInstructionData)
+            );
+        }
+        assert_eq!(
+            Instruction::unpack_from_slice(&[14, 1, 2]),
+            Ok(Instruction::VerifyWinningMove(Move { row: 1, col: 2 }))
+        );
+    }
 }
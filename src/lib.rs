@@ -1,5 +1,7 @@
+pub mod accounts;
 pub mod entrypoint;
 pub mod error;
+pub mod event;
 pub mod instruction;
 pub mod processor;
 pub mod state;
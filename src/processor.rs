@@ -1,21 +1,39 @@
+use crate::accounts::{
+    check_token_account_key, is_token_program, AcceptGameAccounts, AgreeDrawAccounts,
+    BurnTrophyAccounts, CancelGameAccounts, CloseGameAccounts, CreateGameAccounts,
+    DoubleOrNothingAccounts, PlayerGameAccounts, RematchAccounts, SlashBondAccounts,
+};
 use crate::error::Error;
+use crate::event::GameEvent;
 use crate::instruction::Instruction;
-use crate::state::{Game, GameState};
+use crate::state::{
+    Game, GameState, GlobalStats, Move, Stake, Symbol, MAX_BOARD_SIZE, MAX_EXTRA_STAKES, MAX_TILES,
+    SETTLEMENT_WINDOW_SLOTS,
+};
 use borsh::BorshSerialize;
 use solana_program::{
     account_info::{next_account_info, AccountInfo},
-    borsh::try_from_slice_unchecked,
     entrypoint::ProgramResult,
-    program::{invoke, invoke_signed},
+    hash::hash,
+    msg,
+    program::{invoke, invoke_signed, set_return_data},
     program_error::ProgramError,
-    program_pack::{IsInitialized, Pack},
+    program_pack::IsInitialized,
     pubkey::Pubkey,
     system_instruction,
     system_program::ID as SYSTEM_PROGRAM_ID,
-    sysvar::{rent::Rent, Sysvar},
+    sysvar::{clock::Clock, rent::Rent, Sysvar},
+};
+use spl_associated_token_account::{
+    get_associated_token_address_with_program_id, instruction::create_associated_token_account,
+};
+use spl_token_2022::{
+    extension::{BaseStateWithExtensions, ExtensionType, StateWithExtensions},
+    instruction,
+    state::{Account, Mint},
 };
-use spl_token::{instruction, state::Account, ID as TOKEN_PROGRAM_ID};
 use std::convert::TryInto;
+use std::slice::Iter;
 
 pub fn process_instruction(
     program_id: &Pubkey,
@@ -27,393 +45,3873 @@ pub fn process_instruction(
         Instruction::CreateGame {
             player_two,
             stake_amount,
-        } => create_game(program_id, accounts, player_two, stake_amount),
-        Instruction::AcceptGame => accept_game(program_id, accounts),
-        Instruction::PlayGame { row, col } => play_game(program_id, accounts, row, col),
-        Instruction::CloseGame => close_game(program_id, accounts),
+            stake_amount_two,
+            bond_amount,
+            fee_bps,
+            fee_collector,
+            expiry_slots,
+            max_duration_slots,
+            move_timeout_slots,
+            allow_early_draw,
+            higher_staker_moves_second,
+            misere,
+            board_size,
+            win_len,
+            series_target,
+            dedicated_escrow,
+            game_nonce,
+            label,
+            extra_stakes,
+            join_secret_hash,
+        } => create_game(
+            program_id,
+            accounts,
+            player_two,
+            stake_amount,
+            stake_amount_two,
+            bond_amount,
+            fee_bps,
+            fee_collector,
+            expiry_slots,
+            max_duration_slots,
+            move_timeout_slots,
+            allow_early_draw,
+            higher_staker_moves_second,
+            misere,
+            board_size,
+            win_len,
+            series_target,
+            dedicated_escrow,
+            game_nonce,
+            label,
+            &extra_stakes,
+            join_secret_hash,
+        ),
+        Instruction::AcceptGame {
+            fee_bps,
+            fee_collector,
+            join_secret,
+        } => accept_game(program_id, accounts, fee_bps, fee_collector, join_secret),
+        Instruction::PlayGame(play) => play_game(program_id, accounts, play),
+        Instruction::CloseGame {
+            keep_trophy,
+            create_ata_if_missing,
+            rent_recipient,
+        } => close_game(
+            program_id,
+            accounts,
+            keep_trophy,
+            create_ata_if_missing,
+            rent_recipient,
+        ),
         Instruction::CancelGame => cancel_game(program_id, accounts),
+        Instruction::BurnTrophy => burn_trophy(program_id, accounts),
+        Instruction::ForceDraw => force_draw(program_id, accounts),
+        Instruction::InspectEscrow { mint } => inspect_escrow(program_id, accounts, mint),
+        Instruction::ClaimDraw => claim_draw(program_id, accounts),
+        Instruction::GetLegalMoves => get_legal_moves(program_id, accounts),
+        Instruction::DoubleOrNothing => double_or_nothing(program_id, accounts),
+        Instruction::SlashBond => slash_bond(program_id, accounts),
+        Instruction::Resign => resign(program_id, accounts),
+        Instruction::ClaimTimeout => claim_timeout(program_id, accounts),
+        Instruction::VerifyWinningMove(play) => verify_winning_move(program_id, accounts, play),
+        Instruction::Rematch => rematch(program_id, accounts),
+        Instruction::AgreeDraw => agree_draw(program_id, accounts),
+        Instruction::CloseEscrow { mint, game } => close_escrow(program_id, accounts, mint, game),
+        Instruction::LogBoard => log_board(program_id, accounts),
     }
 }
 
+#[allow(clippy::too_many_arguments)]
 fn create_game(
     program_id: &Pubkey,
     accounts: &[AccountInfo],
     player_two: Pubkey,
     stake_amount: u64,
+    stake_amount_two: u64,
+    bond_amount: u64,
+    fee_bps: u16,
+    fee_collector: Pubkey,
+    expiry_slots: u64,
+    max_duration_slots: u64,
+    move_timeout_slots: u64,
+    allow_early_draw: bool,
+    higher_staker_moves_second: bool,
+    misere: bool,
+    board_size: u8,
+    win_len: u8,
+    series_target: u8,
+    dedicated_escrow: bool,
+    game_nonce: Option<u64>,
+    label: [u8; 32],
+    extra_stakes: &[Stake],
+    join_secret_hash: Option<[u8; 32]>,
 ) -> ProgramResult {
     let account_info_iter = &mut accounts.iter();
-    let player = next_account_info(account_info_iter)?;
-    let game_account = next_account_info(account_info_iter)?;
-    let mint = next_account_info(account_info_iter)?;
-    let escrow = next_account_info(account_info_iter)?;
-    let token_account = next_account_info(account_info_iter)?;
-    let token_program = next_account_info(account_info_iter)?;
-    let system_program = next_account_info(account_info_iter)?;
+    let CreateGameAccounts {
+        player,
+        game_account,
+        mint,
+        escrow,
+        token_account,
+        token_program,
+        system_program,
+        stats,
+    } = CreateGameAccounts::from_account_iter(account_info_iter)?;
 
     // data and accounts validation
-    if stake_amount == 0 || player_two == *player.key {
+    // either both players stake or neither does
+    if (stake_amount == 0) != (stake_amount_two == 0) || player_two == *player.key {
         return Err(ProgramError::InvalidArgument);
     }
-    if !player.is_signer || !game_account.is_signer {
-        return Err(ProgramError::MissingRequiredSignature);
+    // a keypair game account signs, a derived one is signed for when it's created
+    let game_bump = match game_nonce {
+        Some(nonce) => {
+            let (game_key, bump) = find_game_address(program_id, player.key, &player_two, nonce);
+            if *game_account.key != game_key {
+                return Err(ProgramError::InvalidArgument);
+            }
+            Some(bump)
+        }
+        None if !game_account.is_signer => return Err(ProgramError::MissingRequiredSignature),
+        None => None,
+    };
+    // only an open challenge can be guarded by a secret, whose hash can't be the all zeros
+    // the account stores for no secret
+    if (player_two != Pubkey::default() && join_secret_hash.is_some())
+        || join_secret_hash == Some([0; 32])
+    {
+        return Err(ProgramError::InvalidInstructionData);
     }
-    if *system_program.key != SYSTEM_PROGRAM_ID || *token_program.key != TOKEN_PROGRAM_ID {
-        return Err(ProgramError::IncorrectProgramId);
+    if extra_stakes.len() > MAX_EXTRA_STAKES {
+        return Err(ProgramError::InvalidInstructionData);
     }
-    let (escrow_key, bump) = Pubkey::find_program_address(
-        &["escrow".as_bytes().as_ref(), mint.key.as_ref()],
-        program_id,
-    );
-    if *escrow.key != escrow_key {
-        return Err(ProgramError::IncorrectProgramId);
+    // a bond only secures the main stake, and leaves nothing to earn yield on
+    if bond_amount > stake_amount
+        || (bond_amount != 0
+            && (!extra_stakes.is_empty() || dedicated_escrow || stake_amount_two != stake_amount))
+    {
+        return Err(ProgramError::InvalidArgument);
     }
-    if *mint.key != TOKEN_PROGRAM_ID || *token_account.key != TOKEN_PROGRAM_ID {
-        return Err(ProgramError::IllegalOwner);
+    if !(3..=MAX_BOARD_SIZE as u8).contains(&board_size) || !(3..=board_size).contains(&win_len) {
+        return Err(Error::InvalidBoardSize.into());
+    }
+    if fee_bps > 10_000 {
+        return Err(Error::InvalidFee.into());
     }
-    let send_account = Account::unpack(&token_account.data.borrow())?;
-    if send_account.mint != *mint.key || send_account.owner != *player.key {
+    // the fee comes out of the escrowed pot, which a bonded game never has
+    if fee_bps != 0 && (bond_amount != 0 || fee_collector == Pubkey::default()) {
         return Err(ProgramError::InvalidArgument);
     }
-    if send_account.amount < stake_amount {
-        return Err(ProgramError::InsufficientFunds);
+    let escrowed_amount = if bond_amount != 0 {
+        bond_amount
+    } else {
+        stake_amount
+    };
+    if std::str::from_utf8(&label).is_err() {
+        return Err(Error::InvalidLabel.into());
     }
-
-    // if escrow account does not exist, create it
-    if escrow.data_is_empty() {
-        let rent_amount = Rent::get()?.minimum_balance(Account::LEN);
-        let (authority, _) =
-            Pubkey::find_program_address(&["authority".as_bytes().as_ref()], program_id);
+    for (i, stake) in extra_stakes.iter().enumerate() {
+        let duplicate = stake.mint == *mint.key
+            || extra_stakes[..i]
+                .iter()
+                .any(|other| other.mint == stake.mint);
+        if stake.amount == 0 || duplicate {
+            return Err(ProgramError::InvalidArgument);
+        }
+    }
+    // lamport stakes only support the plain game, their escrow is per game already
+    let native = *mint.key == SYSTEM_PROGRAM_ID;
+    if native && (!extra_stakes.is_empty() || bond_amount != 0 || dedicated_escrow) {
+        return Err(ProgramError::InvalidArgument);
+    }
+    // a casual game stakes nothing, so it uses the lamport layout and has no pot to take a
+    // fee from
+    let casual = stake_amount == 0;
+    if casual && (!native || fee_bps != 0) {
+        return Err(ProgramError::InvalidArgument);
+    }
+    let (escrow_key, bump) = if native {
+        find_native_escrow_address(program_id, game_account.key)
+    } else {
+        find_escrow_address(
+            program_id,
+            mint.key,
+            dedicated_escrow.then_some(game_account.key),
+        )
+    };
+    if *escrow.key != escrow_key {
+        return Err(ProgramError::IncorrectProgramId);
+    }
+    let (stats_key, stats_bump) = Pubkey::find_program_address(&["stats".as_bytes()], program_id);
+    if *stats.key != stats_key {
+        return Err(ProgramError::InvalidArgument);
+    }
+    if casual {
+        // nothing to escrow, the escrow account is never created
+    } else if native {
+        // the escrow is created holding the stake on top of its own rent
+        let rent_amount = Rent::get()?.minimum_balance(0);
         invoke_signed(
             &system_instruction::create_account(
                 player.key,
                 escrow.key,
-                rent_amount,
-                Account::LEN.try_into().unwrap(),
-                &TOKEN_PROGRAM_ID,
+                rent_amount
+                    .checked_add(escrowed_amount)
+                    .ok_or(Error::Overflow)?,
+                0,
+                program_id,
             ),
-            &[player.clone(), escrow.clone()],
-            &[&["escrow".as_bytes().as_ref(), mint.key.as_ref(), &[bump]]],
+            &[player.clone(), escrow.clone(), system_program.clone()],
+            &[&["sol_escrow".as_bytes(), game_account.key.as_ref(), &[bump]]],
         )?;
+    } else {
+        // the mint decides which token program the game uses from now on
+        if mint.owner != token_program.key || token_account.owner != token_program.key {
+            return Err(ProgramError::IllegalOwner);
+        }
+        let send_account = unpack_token_account(token_account)?;
+        if send_account.mint != *mint.key || send_account.owner != *player.key {
+            return Err(ProgramError::InvalidArgument);
+        }
+        if send_account.amount < escrowed_amount {
+            return Err(ProgramError::InsufficientFunds);
+        }
+
+        // if escrow account does not exist, create it
+        if escrow.data_is_empty() {
+            let bump_seed = [bump];
+            let mut escrow_seeds = vec!["escrow".as_bytes(), mint.key.as_ref()];
+            if dedicated_escrow {
+                escrow_seeds.push(game_account.key.as_ref());
+            }
+            escrow_seeds.push(&bump_seed);
+            let escrow_len = escrow_len(mint)?;
+            let rent_amount = Rent::get()?.minimum_balance(escrow_len);
+            let (authority, _) =
+                Pubkey::find_program_address(&["authority".as_bytes()], program_id);
+            invoke_signed(
+                &system_instruction::create_account(
+                    player.key,
+                    escrow.key,
+                    rent_amount,
+                    escrow_len.try_into().unwrap(),
+                    token_program.key,
+                ),
+                &[player.clone(), escrow.clone()],
+                &[&escrow_seeds],
+            )?;
+            invoke(
+                &instruction::initialize_account3(
+                    token_program.key,
+                    escrow.key,
+                    mint.key,
+                    &authority,
+                )?,
+                &[escrow.clone(), mint.clone()],
+            )?;
+        }
+
+        // transfer the stake tokens
         invoke(
-            &instruction::initialize_account3(&TOKEN_PROGRAM_ID, escrow.key, mint.key, &authority)?,
-            &[escrow.clone(), mint.clone()],
+            &transfer_instruction(
+                token_program.key,
+                token_account.key,
+                escrow.key,
+                player.key,
+                escrowed_amount,
+            )?,
+            &[token_account.clone(), escrow.clone(), player.clone()],
         )?;
     }
 
-    // transfer the stake tokens
-    invoke(
-        &instruction::transfer(
-            &TOKEN_PROGRAM_ID,
-            token_account.key,
-            escrow.key,
-            player.key,
-            &[],
-            stake_amount,
-        )?,
-        &[token_account.clone(), escrow.clone(), player.clone()],
-    )?;
+    // escrow the rest of the basket
+    for stake in extra_stakes {
+        let mint = next_account_info(account_info_iter)?;
+        let escrow = next_account_info(account_info_iter)?;
+        let token_account = next_account_info(account_info_iter)?;
+        if *mint.key != stake.mint {
+            return Err(ProgramError::InvalidArgument);
+        }
+        if !is_token_program(mint.owner) {
+            return Err(ProgramError::IllegalOwner);
+        }
+        create_escrow_if_needed(program_id, player, mint, escrow)?;
+        deposit_stake(program_id, player, escrow, token_account, stake)?;
+    }
 
     // create and initialize the game account
+    // an address already in use fails the create account, so a nonce can't be reused
     let rent_amount = Rent::get()?.minimum_balance(Game::LEN);
-    invoke(
-        &system_instruction::create_account(
-            player.key,
-            game_account.key,
-            rent_amount,
-            Game::LEN.try_into().unwrap(),
-            program_id,
-        ),
-        &[player.clone(), game_account.clone()],
-    )?;
-    let mut game = try_from_slice_unchecked::<Game>(&game_account.data.borrow()).unwrap();
+    let create_game_account = system_instruction::create_account(
+        player.key,
+        game_account.key,
+        rent_amount,
+        Game::LEN.try_into().unwrap(),
+        program_id,
+    );
+    match game_nonce.zip(game_bump) {
+        Some((nonce, bump)) => invoke_signed(
+            &create_game_account,
+            &[player.clone(), game_account.clone()],
+            &[&[
+                "game".as_bytes(),
+                player.key.as_ref(),
+                player_two.as_ref(),
+                &nonce.to_le_bytes(),
+                &[bump],
+            ]],
+        )?,
+        None => invoke(
+            &create_game_account,
+            &[player.clone(), game_account.clone()],
+        )?,
+    }
+    if game_account.data_len() != Game::LEN {
+        return Err(ProgramError::InvalidAccountData);
+    }
+    let mut game = Game::load(&game_account.data.borrow())?;
     if game.is_initialized() {
         return Err(ProgramError::InvalidAccountData);
     }
+    game.version = Game::VERSION;
     game.players = [*player.key, player_two];
-    game.board = [[None; 3]; 3];
+    game.board = [[None; MAX_BOARD_SIZE]; MAX_BOARD_SIZE];
+    game.move_history = [Game::NO_MOVE; MAX_TILES];
+    game.size = board_size;
+    game.win_len = win_len;
+    game.series_target = series_target;
     game.state = GameState::Unaccepted;
     game.turns = 0;
     game.stake_mint = *mint.key;
+    game.token_program = *token_program.key;
     game.stake_amount = stake_amount;
+    game.stake_amount_two = stake_amount_two;
+    game.deposits = [escrowed_amount, 0];
+    game.bond_amount = bond_amount;
+    game.fee_bps = fee_bps;
+    game.fee_collector = fee_collector;
+    game.join_secret_hash = join_secret_hash.unwrap_or_default();
+    game.created_slot = Clock::get()?.slot;
+    game.game_id = Game::derive_id(&game.players, game.created_slot);
+    game.accept_deadline = if expiry_slots == 0 {
+        0
+    } else {
+        game.created_slot
+            .checked_add(expiry_slots)
+            .ok_or(Error::Overflow)?
+    };
+    game.max_duration_slots = max_duration_slots;
+    game.move_timeout_slots = move_timeout_slots;
+    game.last_move_slot = game.created_slot;
+    game.started_slot = 0;
+    game.allow_early_draw = allow_early_draw;
+    game.higher_staker_moves_second = higher_staker_moves_second;
+    game.first_player = 0;
+    game.misere = misere;
+    game.dedicated_escrow = dedicated_escrow;
+    game.last_mover = Pubkey::default();
+    game.forfeited = false;
+    game.label = label;
+    msg!("game id: {}", game.id());
+    msg!("label: {}", game.label()?);
+    game.extra_stakes = Default::default();
+    game.extra_stakes[..extra_stakes.len()].copy_from_slice(extra_stakes);
+    game.extra_stake_count = extra_stakes.len() as u8;
     game.is_initialized = true;
     game.serialize(&mut &mut game_account.data.borrow_mut()[..])
-        .unwrap();
+        .map_err(|_| ProgramError::AccountDataTooSmall)?;
 
-    Ok(())
+    // if stats account does not exist, create it
+    if stats.data_is_empty() {
+        let rent_amount = Rent::get()?.minimum_balance(GlobalStats::LEN);
+        invoke_signed(
+            &system_instruction::create_account(
+                player.key,
+                stats.key,
+                rent_amount,
+                GlobalStats::LEN.try_into().unwrap(),
+                program_id,
+            ),
+            &[player.clone(), stats.clone()],
+            &[&["stats".as_bytes(), &[stats_bump]]],
+        )?;
+    }
+
+    // update the global stats
+    let mut global_stats = GlobalStats::load(&stats.data.borrow())?;
+    global_stats.is_initialized = true;
+    global_stats.games_created = global_stats
+        .games_created
+        .checked_add(1)
+        .ok_or(Error::Overflow)?;
+    global_stats.total_staked = global_stats
+        .total_staked
+        .checked_add(escrowed_amount)
+        .ok_or(Error::Overflow)?;
+    global_stats
+        .serialize(&mut &mut stats.data.borrow_mut()[..])
+        .map_err(|_| ProgramError::AccountDataTooSmall)?;
+
+    GameEvent::Created {
+        game_id: game.game_id,
+        players: game.players,
+        stake_mint: game.stake_mint,
+        stake_amount: game.stake_amount,
+    }
+    .emit()
 }
 
-fn accept_game(program_id: &Pubkey, accounts: &[AccountInfo]) -> ProgramResult {
+fn accept_game(
+    program_id: &Pubkey,
+    accounts: &[AccountInfo],
+    fee_bps: u16,
+    fee_collector: Pubkey,
+    join_secret: Option<[u8; 32]>,
+) -> ProgramResult {
     let account_info_iter = &mut accounts.iter();
-    let player_two = next_account_info(account_info_iter)?;
-    let game_account = next_account_info(account_info_iter)?;
-    let escrow = next_account_info(account_info_iter)?;
-    let token_account = next_account_info(account_info_iter)?;
-    let token_program = next_account_info(account_info_iter)?;
+    let AcceptGameAccounts {
+        player_two,
+        game_account,
+        escrow,
+        token_account,
+        token_program,
+    } = AcceptGameAccounts::from_account_iter(account_info_iter)?;
 
     // account validation
-    if !player_two.is_signer {
-        return Err(ProgramError::MissingRequiredSignature);
-    }
-    if game_account.owner != program_id || *token_account.owner != TOKEN_PROGRAM_ID {
+    if game_account.owner != program_id {
         return Err(ProgramError::IllegalOwner);
     }
-    if *token_program.key != TOKEN_PROGRAM_ID {
-        return Err(ProgramError::IncorrectProgramId);
-    }
-    let send_account = Account::unpack(&token_account.data.borrow())?;
-    let mut game = try_from_slice_unchecked::<Game>(&game_account.data.borrow()).unwrap();
+    let mut game = Game::load(&game_account.data.borrow())?;
     if !game.is_initialized() {
         return Err(ProgramError::UninitializedAccount);
     }
-    if game.players[1] != *player_two.key {
+    game.check_version()?;
+    // the creator can never take the other seat, whatever the game account says
+    if *player_two.key == game.players[0] {
+        return Err(Error::UnauthorizedToAccept.into());
+    }
+    // the fee is whatever the creator asked for, so the accepter has to have agreed to it
+    if game.fee_bps != fee_bps || (fee_bps != 0 && game.fee_collector != fee_collector) {
+        return Err(Error::FeeMismatch.into());
+    }
+    if game.players[1] == Pubkey::default() {
+        // open challenge, the seat goes to whoever knows the join secret, or to the first
+        // taker when there is no secret
+        if game.join_secret_hash != [0; 32] {
+            let join_secret = join_secret.ok_or(Error::InvalidJoinSecret)?;
+            if hash(&join_secret).to_bytes() != game.join_secret_hash {
+                return Err(Error::InvalidJoinSecret.into());
+            }
+        }
+        game.players[1] = *player_two.key;
+    } else if game.players[1] != *player_two.key {
         return Err(Error::UnauthorizedToAccept.into());
     }
     if game.state != GameState::Unaccepted {
         return Err(Error::AlreadyAccepted.into());
     }
-    if send_account.owner != *player_two.key {
-        return Err(ProgramError::InvalidArgument);
-    }
-    if send_account.amount < game.stake_amount {
-        return Err(ProgramError::InsufficientFunds);
+    // no move can be made before acceptance, a filled board means a tampered account
+    if game.turns != 0 || game.board.iter().flatten().any(|tile| tile.is_some()) {
+        return Err(ProgramError::InvalidAccountData);
     }
-    let (escrow_key, _) = Pubkey::find_program_address(
-        &["escrow".as_bytes().as_ref(), game.stake_mint.as_ref()],
-        program_id,
-    );
-    if *escrow.key != escrow_key {
-        return Err(ProgramError::InvalidArgument);
+    if game.accept_deadline != 0 && Clock::get()?.slot > game.accept_deadline {
+        return Err(Error::ChallengeExpired.into());
     }
+    // a native game takes the system program after the fixed accounts to move lamports
+    let system_program = if game.is_native() {
+        let system_program = next_account_info(account_info_iter)?;
+        if *system_program.key != SYSTEM_PROGRAM_ID {
+            return Err(ProgramError::IncorrectProgramId);
+        }
+        if !game.is_casual() {
+            verify_native_escrow(program_id, game_account.key, escrow)?;
+        }
+        if player_two.lamports() < game.escrowed_amount(1) {
+            return Err(ProgramError::InsufficientFunds);
+        }
+        Some(system_program)
+    } else {
+        // the game sticks to the token program it was created with
+        if *token_program.key != game.token_program {
+            return Err(ProgramError::IncorrectProgramId);
+        }
+        if token_account.owner != token_program.key {
+            return Err(ProgramError::IllegalOwner);
+        }
+        // the escrow has to belong to the token program that is going to move its tokens
+        if escrow.owner != token_program.key {
+            return Err(ProgramError::IncorrectProgramId);
+        }
+        let send_account = unpack_token_account(token_account)?;
+        if send_account.owner != *player_two.key {
+            return Err(ProgramError::InvalidArgument);
+        }
+        if send_account.amount < game.escrowed_amount(1) {
+            return Err(ProgramError::InsufficientFunds);
+        }
+        let (escrow_key, _) = find_escrow_address(
+            program_id,
+            &game.stake_mint,
+            game.dedicated_escrow.then_some(game_account.key),
+        );
+        if *escrow.key != escrow_key {
+            return Err(ProgramError::InvalidArgument);
+        }
+        // the escrow has to be a live token account of the stake mint held by the authority
+        let escrow_account = unpack_token_account(escrow)?;
+        let (authority, _) = Pubkey::find_program_address(&["authority".as_bytes()], program_id);
+        if escrow_account.mint != game.stake_mint || escrow_account.owner != authority {
+            return Err(ProgramError::InvalidAccountData);
+        }
+        None
+    };
 
-    // transfer stake tokens
-    invoke(
-        &instruction::transfer(
-            &TOKEN_PROGRAM_ID,
-            token_account.key,
-            &escrow_key,
-            player_two.key,
-            &[],
-            game.stake_amount,
-        )?,
-        &[token_account.clone(), escrow.clone(), player_two.clone()],
-    )?;
-
-    // update and save the game account
+    // update and save the game account before any cpi, so that a reentrant call sees it accepted
     game.state = GameState::Ongoing;
+    game.started_slot = Clock::get()?.slot;
+    game.last_move_slot = game.started_slot;
+    game.deposits[1] = game.escrowed_amount(1);
+    game.assign_first_player(game.deposits);
+    // a failed write has to fail the whole instruction, which reverts the transfers below too
     game.serialize(&mut &mut game_account.data.borrow_mut()[..])
-        .unwrap();
+        .map_err(|_| ProgramError::AccountDataTooSmall)?;
 
-    Ok(())
+    // transfer the stake
+    if game.escrowed_amount(1) == 0 {
+        // a zero-stake game has nothing to escrow, so no cpi is made for it
+    } else if let Some(system_program) = system_program {
+        invoke(
+            &system_instruction::transfer(player_two.key, escrow.key, game.escrowed_amount(1)),
+            &[player_two.clone(), escrow.clone(), system_program.clone()],
+        )?;
+    } else {
+        invoke(
+            &transfer_instruction(
+                &game.token_program,
+                token_account.key,
+                escrow.key,
+                player_two.key,
+                game.escrowed_amount(1),
+            )?,
+            &[token_account.clone(), escrow.clone(), player_two.clone()],
+        )?;
+    }
+    for stake in game.basket() {
+        let escrow = next_account_info(account_info_iter)?;
+        let token_account = next_account_info(account_info_iter)?;
+        deposit_stake(program_id, player_two, escrow, token_account, stake)?;
+    }
+
+    // let the accepting client know the starting position right away
+    GameEvent::Accepted(game.snapshot()).emit()
 }
 
-fn play_game(
-    program_id: &Pubkey,
-    accounts: &[AccountInfo],
-    row: usize,
-    col: usize,
-) -> ProgramResult {
+fn play_game(program_id: &Pubkey, accounts: &[AccountInfo], play: Move) -> ProgramResult {
     let account_info_iter = &mut accounts.iter();
-    let player = next_account_info(account_info_iter)?;
-    let game_account = next_account_info(account_info_iter)?;
+    let PlayerGameAccounts {
+        player,
+        game_account,
+    } = PlayerGameAccounts::from_account_iter(account_info_iter)?;
 
     // account validation
-    if !player.is_signer {
-        return Err(ProgramError::MissingRequiredSignature);
-    }
     if game_account.owner != program_id {
         return Err(ProgramError::IllegalOwner);
     }
-    let mut game = try_from_slice_unchecked::<Game>(&game_account.data.borrow()).unwrap();
+    let mut game = Game::load(&game_account.data.borrow())?;
+    game.check_version()?;
+    if game.players[0] == game.players[1] {
+        return Err(ProgramError::InvalidAccountData);
+    }
 
     // play the game
-    game.play(player.key, row, col)?;
+    let symbol = game.current_symbol();
+    game.play(player.key, play.row as usize, play.col as usize)?;
+    let round = game.next_round();
+    game.last_move_slot = Clock::get()?.slot;
+    game.serialize(&mut &mut game_account.data.borrow_mut()[..])
+        .map_err(|_| ProgramError::AccountDataTooSmall)?;
 
-    Ok(())
+    GameEvent::Moved {
+        game_id: game.game_id,
+        player: *player.key,
+        play,
+        symbol,
+        turns: game.turns,
+    }
+    .emit()?;
+    // a game ending move is followed by its result, which is what the return data holds
+    if let Some(round) = round {
+        return GameEvent::RoundEnded {
+            game_id: game.game_id,
+            winner: match round {
+                GameState::Over { winner } => Some(winner),
+                _ => None,
+            },
+            wins: game.wins,
+        }
+        .emit();
+    }
+    match game.state {
+        GameState::Over { winner } => GameEvent::Won {
+            game_id: game.game_id,
+            winner,
+        }
+        .emit(),
+        GameState::Draw => GameEvent::Drawn {
+            game_id: game.game_id,
+        }
+        .emit(),
+        _ => Ok(()),
+    }
 }
 
-fn close_game(program_id: &Pubkey, accounts: &[AccountInfo]) -> ProgramResult {
+fn close_game(
+    program_id: &Pubkey,
+    accounts: &[AccountInfo],
+    keep_trophy: bool,
+    create_ata_if_missing: bool,
+    rent_recipient: Option<Pubkey>,
+) -> ProgramResult {
     let account_info_iter = &mut accounts.iter();
-    let player_one = next_account_info(account_info_iter)?;
-    let game_account = next_account_info(account_info_iter)?;
-    let escrow = next_account_info(account_info_iter)?;
-    let authority = next_account_info(account_info_iter)?;
-    let token_program = next_account_info(account_info_iter)?;
-    let system_program = next_account_info(account_info_iter)?;
+    let CloseGameAccounts {
+        player_one,
+        game_account,
+        escrow,
+        authority,
+        token_program,
+        system_program,
+        stats,
+    } = CloseGameAccounts::from_account_iter(account_info_iter)?;
 
     // account validation
-    if game_account.owner != program_id {
-        return Err(ProgramError::IllegalOwner);
-    }
-    if *escrow.owner != TOKEN_PROGRAM_ID {
+    if game_account.owner != program_id || stats.owner != program_id {
         return Err(ProgramError::IllegalOwner);
     }
-    if *token_program.key != TOKEN_PROGRAM_ID || *system_program.key != SYSTEM_PROGRAM_ID {
-        return Err(ProgramError::IncorrectProgramId);
-    }
-    let (authority_key, bump) =
-        Pubkey::find_program_address(&["authority".as_bytes().as_ref()], program_id);
-    if *authority.key != authority_key {
+    let (stats_key, _) = Pubkey::find_program_address(&["stats".as_bytes()], program_id);
+    if *stats.key != stats_key {
         return Err(ProgramError::InvalidArgument);
     }
-    let game = try_from_slice_unchecked::<Game>(&game_account.data.borrow()).unwrap();
+    let mut game = Game::load(&game_account.data.borrow())?;
+    game.check_version()?;
+    if game.players[0] == game.players[1] {
+        return Err(ProgramError::InvalidAccountData);
+    }
     if *player_one.key != game.players[0] {
         return Err(ProgramError::InvalidArgument);
     }
-    let (escrow_key, _) = Pubkey::find_program_address(
-        &["escrow".as_bytes().as_ref(), game.stake_mint.as_ref()],
-        program_id,
-    );
-    if *escrow.key != escrow_key {
-        return Err(ProgramError::InvalidArgument);
+    if !player_one.is_signer {
+        return Err(ProgramError::MissingRequiredSignature);
     }
+    // a native game has no token escrow, so no authority to sign for it either. a casual
+    // game has no escrow at all
+    let authority_bump = if game.is_native() {
+        if !game.is_casual() {
+            verify_native_escrow(program_id, game_account.key, escrow)?;
+        }
+        None
+    } else {
+        if *token_program.key != game.token_program || escrow.owner != token_program.key {
+            return Err(ProgramError::IncorrectProgramId);
+        }
+        let (_, bump) = verify_pdas(
+            program_id,
+            &game.stake_mint,
+            game.dedicated_escrow.then_some(game_account.key),
+            escrow,
+            authority,
+        )?;
+        Some(bump)
+    };
 
     // check game state and close logic
+    let mut winner_authorized = false;
     if let GameState::Unaccepted = game.state {
-        return Err(Error::UnacceptedGame.into());
+        // given the creator's refund account after the fixed ones, closing works like a cancel
+        let remaining = account_info_iter.as_slice();
+        if remaining.is_empty() {
+            return Err(Error::UnacceptedGame.into());
+        }
+        let mut cancel_accounts = vec![
+            player_one.clone(),
+            game_account.clone(),
+            escrow.clone(),
+            remaining[0].clone(),
+            authority.clone(),
+            token_program.clone(),
+        ];
+        cancel_accounts.extend_from_slice(&remaining[1..]);
+        return cancel_game(program_id, &cancel_accounts);
     } else if let GameState::Ongoing = game.state {
         return Err(Error::OngoingGame.into());
-    } else if let GameState::Draw = game.state {
-        let token_account_one = next_account_info(account_info_iter)?;
-        let token_account_two = next_account_info(account_info_iter)?;
-
-        if *token_account_one.owner != TOKEN_PROGRAM_ID
-            || *token_account_two.owner != TOKEN_PROGRAM_ID
-        {
-            return Err(ProgramError::InvalidArgument);
+    } else if let GameState::Settled { .. } = game.state {
+        return Err(Error::AlreadySettled.into());
+    } else if let GameState::Cancelled = game.state {
+        return Err(Error::UnclosableGame.into());
+    } else if let Some(bump) = authority_bump {
+        // one payout account per recipient, in the order of game.payouts()
+        let winner = match game.state {
+            GameState::Over { winner } => Some(winner),
+            _ => None,
+        };
+        let escrow_balance = unpack_token_account(escrow)?.amount;
+        let payouts = if game.dedicated_escrow {
+            // everything the game's own escrow holds is attributable to it
+            game.payouts_from_balance(escrow_balance)
+        } else {
+            game.payouts()
+        };
+        // the shared escrow must still hold what this game is owed
+        let owed = payouts
+            .iter()
+            .try_fold(0u64, |total, &(_, amount)| total.checked_add(amount))
+            .ok_or(Error::Overflow)?;
+        if escrow_balance < owed {
+            return Err(ProgramError::InsufficientFunds);
+        }
+        for (recipient, amount) in payouts {
+            let is_winner = winner == Some(recipient);
+            let token_account = next_account_info(account_info_iter)?;
+            check_token_account_key(token_account)?;
+            if is_winner && create_ata_if_missing {
+                create_winner_ata_if_missing(
+                    account_info_iter,
+                    player_one,
+                    token_account,
+                    &recipient,
+                    &game.stake_mint,
+                    token_program,
+                    system_program,
+                )?;
+            }
+            let receive_account = unpack_payout_account(token_account, &game.stake_mint)?;
+            if receive_account.owner != recipient {
+                if !is_winner {
+                    return Err(ProgramError::InvalidArgument);
+                }
+                // payout to someone else's token account must be authorized by the winner
+                let winner_account = next_account_info(account_info_iter)?;
+                if *winner_account.key != recipient {
+                    return Err(ProgramError::InvalidArgument);
+                }
+                if !winner_account.is_signer {
+                    return Err(ProgramError::MissingRequiredSignature);
+                }
+                winner_authorized = true;
+            }
+            invoke_signed(
+                &transfer_instruction(
+                    &game.token_program,
+                    escrow.key,
+                    token_account.key,
+                    authority.key,
+                    amount,
+                )?,
+                &[escrow.clone(), token_account.clone(), authority.clone()],
+                &[&["authority".as_bytes(), &[bump]]],
+            )?;
         }
-        let receive_account_one = Account::unpack(&token_account_one.data.borrow())?;
-        let receive_account_two = Account::unpack(&token_account_two.data.borrow())?;
 
-        if receive_account_one.owner != game.players[0]
-            || receive_account_two.owner != game.players[1]
-        {
-            return Err(ProgramError::InvalidArgument);
+        // in a bonded game the loser pays the stake out of their own account
+        if let (Some(winner), true) = (winner, game.bond_amount != 0) {
+            let loser = next_account_info(account_info_iter)?;
+            let loser_stake_account = next_account_info(account_info_iter)?;
+            let winner_stake_account = next_account_info(account_info_iter)?;
+            if *loser.key != game.opponent(&winner) {
+                return Err(ProgramError::InvalidArgument);
+            }
+            if !loser.is_signer {
+                return Err(ProgramError::MissingRequiredSignature);
+            }
+            check_token_account_key(loser_stake_account)?;
+            let receive_account = unpack_payout_account(winner_stake_account, &game.stake_mint)?;
+            if receive_account.owner != winner {
+                return Err(ProgramError::InvalidArgument);
+            }
+            invoke(
+                &transfer_instruction(
+                    &game.token_program,
+                    loser_stake_account.key,
+                    winner_stake_account.key,
+                    loser.key,
+                    game.stake_amount,
+                )?,
+                &[
+                    loser_stake_account.clone(),
+                    winner_stake_account.clone(),
+                    loser.clone(),
+                ],
+            )?;
         }
-        if receive_account_one.mint != game.stake_mint
-            || receive_account_two.mint != game.stake_mint
-        {
-            return Err(ProgramError::InvalidArgument);
+    } else if game.is_casual() {
+        // nothing was staked, so no payout account follows
+    } else {
+        // lamports are paid straight to the wallets, one per recipient of game.payouts()
+        let escrow_balance = escrow
+            .lamports()
+            .saturating_sub(Rent::get()?.minimum_balance(0));
+        let payouts = game.payouts();
+        let owed = payouts
+            .iter()
+            .try_fold(0u64, |total, &(_, amount)| total.checked_add(amount))
+            .ok_or(Error::Overflow)?;
+        if escrow_balance < owed {
+            return Err(ProgramError::InsufficientFunds);
         }
-        invoke_signed(
-            &instruction::transfer(
-                &TOKEN_PROGRAM_ID,
-                &escrow_key,
-                token_account_one.key,
-                &authority_key,
-                &[],
-                game.stake_amount,
-            )?,
-            &[escrow.clone(), token_account_one.clone(), authority.clone()],
-            &[&["authority".as_bytes().as_ref(), &[bump]]],
-        )?;
-        invoke_signed(
-            &instruction::transfer(
-                &TOKEN_PROGRAM_ID,
-                &escrow_key,
-                token_account_two.key,
-                &authority_key,
-                &[],
-                game.stake_amount,
-            )?,
-            &[escrow.clone(), token_account_two.clone(), authority.clone()],
-            &[&["authority".as_bytes().as_ref(), &[bump]]],
-        )?;
-    } else if let GameState::Over { winner } = game.state {
-        let token_account = next_account_info(account_info_iter)?;
-        if *token_account.owner != TOKEN_PROGRAM_ID {
-            return Err(ProgramError::IllegalOwner);
+        for (recipient, amount) in payouts {
+            let wallet = next_account_info(account_info_iter)?;
+            if *wallet.key != recipient {
+                return Err(ProgramError::InvalidArgument);
+            }
+            transfer_lamports(escrow, wallet, amount)?;
+        }
+        // the emptied escrow's rent goes back to the creator who paid it
+        transfer_lamports(escrow, player_one, escrow.lamports())?;
+    }
+
+    // settle the rest of the basket the same way
+    for stake in game.basket() {
+        let escrow = next_account_info(account_info_iter)?;
+        if let GameState::Over { winner } = game.state {
+            let token_account = next_account_info(account_info_iter)?;
+            if create_ata_if_missing {
+                create_winner_ata_if_missing(
+                    account_info_iter,
+                    player_one,
+                    token_account,
+                    &winner,
+                    &stake.mint,
+                    token_program,
+                    system_program,
+                )?;
+            }
+            let receive_account = unpack_payout_account(token_account, &stake.mint)?;
+            if receive_account.owner != winner && !winner_authorized {
+                return Err(ProgramError::MissingRequiredSignature);
+            }
+            release_stake(
+                program_id,
+                escrow,
+                token_account,
+                authority,
+                stake,
+                stake.amount.checked_mul(2).ok_or(Error::Overflow)?,
+            )?;
+        } else {
+            let token_account_one = next_account_info(account_info_iter)?;
+            let token_account_two = next_account_info(account_info_iter)?;
+            let receive_account_one = unpack_payout_account(token_account_one, &stake.mint)?;
+            let receive_account_two = unpack_payout_account(token_account_two, &stake.mint)?;
+            if receive_account_one.owner != game.players[0]
+                || receive_account_two.owner != game.players[1]
+            {
+                return Err(ProgramError::InvalidArgument);
+            }
+            release_stake(
+                program_id,
+                escrow,
+                token_account_one,
+                authority,
+                stake,
+                stake.amount,
+            )?;
+            release_stake(
+                program_id,
+                escrow,
+                token_account_two,
+                authority,
+                stake,
+                stake.amount,
+            )?;
         }
-        let receive_account = Account::unpack(&token_account.data.borrow())?;
-        if receive_account.owner != winner || receive_account.mint != game.stake_mint {
+    }
+
+    count_settled_game(stats)?;
+
+    let winner = if let GameState::Over { winner } = game.state {
+        Some(winner)
+    } else {
+        None
+    };
+    if keep_trophy {
+        // the account stays alive as a record of the result, burn_trophy reclaims the rent later
+        game.state = GameState::Settled { winner };
+        game.serialize(&mut &mut game_account.data.borrow_mut()[..])
+            .map_err(|_| ProgramError::AccountDataTooSmall)?;
+    } else if let Some(rent_recipient) = rent_recipient {
+        let recipient = next_account_info(account_info_iter)?;
+        if *recipient.key != rent_recipient || !game.players.contains(recipient.key) {
             return Err(ProgramError::InvalidArgument);
         }
-        invoke_signed(
-            &instruction::transfer(
-                &TOKEN_PROGRAM_ID,
-                &escrow_key,
-                token_account.key,
-                &authority_key,
-                &[],
-                2 * game.stake_amount,
-            )?,
-            &[escrow.clone(), token_account.clone(), authority.clone()],
-            &[&["authority".as_bytes().as_ref(), &[bump]]],
-        )?;
+        if !recipient.is_signer {
+            return Err(ProgramError::MissingRequiredSignature);
+        }
+        transfer_lamports(game_account, recipient, game_account.lamports())?;
+    } else {
+        transfer_lamports(game_account, player_one, game_account.lamports())?;
     }
-    let game_account_balance = game_account.lamports();
-    **game_account.try_borrow_mut_lamports()? -= game_account_balance;
-    **player_one.try_borrow_mut_lamports()? += game_account_balance;
 
-    Ok(())
+    GameEvent::Settled {
+        game_id: game.game_id,
+        winner,
+    }
+    .emit()
 }
 
 fn cancel_game(program_id: &Pubkey, accounts: &[AccountInfo]) -> ProgramResult {
     let account_info_iter = &mut accounts.iter();
-    let player_one = next_account_info(account_info_iter)?;
-    let game_account = next_account_info(account_info_iter)?;
-    let escrow = next_account_info(account_info_iter)?;
-    let token_account = next_account_info(account_info_iter)?;
-    let authority = next_account_info(account_info_iter)?;
-    let token_program = next_account_info(account_info_iter)?;
+    let CancelGameAccounts {
+        player_one,
+        game_account,
+        escrow,
+        token_account,
+        authority,
+        token_program,
+    } = CancelGameAccounts::from_account_iter(account_info_iter)?;
 
     // account validation
-    if !player_one.is_signer {
-        return Err(ProgramError::MissingRequiredSignature);
-    }
     if game_account.owner != program_id {
         return Err(ProgramError::IllegalOwner);
     }
-    if *escrow.owner != TOKEN_PROGRAM_ID || *token_account.owner != TOKEN_PROGRAM_ID {
-        return Err(ProgramError::InvalidArgument);
-    }
-    let (authority_key, bump) =
-        Pubkey::find_program_address(&["authority".as_bytes().as_ref()], program_id);
-    if *authority.key != authority_key {
-        return Err(ProgramError::InvalidArgument);
-    }
-    if *token_program.key != TOKEN_PROGRAM_ID {
-        return Err(ProgramError::IncorrectProgramId);
-    }
-    let game = try_from_slice_unchecked::<Game>(&game_account.data.borrow()).unwrap();
+    let mut game = Game::load(&game_account.data.borrow())?;
+    game.check_version()?;
     if game.state != GameState::Unaccepted {
         return Err(Error::UnclosableGame.into());
     }
     if *player_one.key != game.players[0] {
         return Err(Error::UnauthorizedToClose.into());
     }
-    let (escrow_key, _) = Pubkey::find_program_address(
-        &["escrow".as_bytes().as_ref(), game.stake_mint.as_ref()],
-        program_id,
-    );
-    if *escrow.key != escrow_key {
-        return Err(ProgramError::InvalidArgument);
-    }
-    let receive_account = Account::unpack(&token_account.data.borrow())?;
-    if receive_account.owner != *player_one.key {
-        return Err(ProgramError::InvalidArgument);
-    }
-    if receive_account.mint != game.stake_mint {
-        return Err(ProgramError::InvalidArgument);
+    if !player_one.is_signer {
+        // the opponent can clean up a challenge that expired without being accepted
+        let player_two = next_account_info(account_info_iter)?;
+        if !player_two.is_signer {
+            return Err(ProgramError::MissingRequiredSignature);
+        }
+        if *player_two.key != game.players[1] {
+            return Err(Error::UnauthorizedToClose.into());
+        }
+        if game.accept_deadline == 0 || Clock::get()?.slot <= game.accept_deadline {
+            return Err(Error::ChallengeNotExpired.into());
+        }
     }
-
-    // transfer tokens back to user
-    invoke_signed(
-        &instruction::transfer(
-            &TOKEN_PROGRAM_ID,
-            &escrow_key,
-            token_account.key,
-            &authority_key,
-            &[],
-            game.stake_amount,
-        )?,
-        &[escrow.clone(), token_account.clone(), authority.clone()],
-        &[&["authority".as_bytes().as_ref(), &[bump]]],
-    )?;
-
-    // transfer lamports from game account to user
-    let game_account_balance = game_account.lamports();
-    **game_account.try_borrow_mut_lamports()? -= game_account_balance;
-    **player_one.try_borrow_mut_lamports()? += game_account_balance;
-
+    // a native game refunds to the creator's wallet, the token account is unused. a casual
+    // game has nothing to refund
+    let authority_bump = if game.is_native() {
+        if !game.is_casual() {
+            verify_native_escrow(program_id, game_account.key, escrow)?;
+        }
+        None
+    } else {
+        if *token_program.key != game.token_program {
+            return Err(ProgramError::IncorrectProgramId);
+        }
+        if escrow.owner != token_program.key || token_account.owner != token_program.key {
+            return Err(ProgramError::InvalidArgument);
+        }
+        let (_, bump) = verify_pdas(
+            program_id,
+            &game.stake_mint,
+            game.dedicated_escrow.then_some(game_account.key),
+            escrow,
+            authority,
+        )?;
+        let receive_account = unpack_token_account(token_account)?;
+        if receive_account.owner != *player_one.key {
+            return Err(ProgramError::InvalidArgument);
+        }
+        if receive_account.mint != game.stake_mint {
+            return Err(ProgramError::InvalidArgument);
+        }
+        // an escrow short of the deposit fails here rather than in the refund cpi
+        if unpack_token_account(escrow)?.amount < game.deposits[0] {
+            return Err(ProgramError::InsufficientFunds);
+        }
+        Some(bump)
+    };
+    // record the outcome before anything is moved, a later failure reverts it anyway
+    game.state = GameState::Cancelled;
+    game.serialize(&mut &mut game_account.data.borrow_mut()[..])
+        .map_err(|_| ProgramError::AccountDataTooSmall)?;
+
+    // the rest of the basket is validated as well before anything is moved
+    let mut basket_accounts = Vec::with_capacity(game.basket().len());
+    for stake in game.basket() {
+        let escrow = next_account_info(account_info_iter)?;
+        let token_account = next_account_info(account_info_iter)?;
+        verify_pdas(program_id, &stake.mint, None, escrow, authority)?;
+        let receive_account = unpack_payout_account(token_account, &stake.mint)?;
+        if receive_account.owner != *player_one.key {
+            return Err(ProgramError::InvalidArgument);
+        }
+        basket_accounts.push((stake, escrow, token_account));
+    }
+
+    // transfer tokens back to user, a dedicated escrow is emptied
+    if let Some(bump) = authority_bump {
+        let refund = if game.dedicated_escrow {
+            unpack_token_account(escrow)?.amount
+        } else {
+            game.deposits[0]
+        };
+        invoke_signed(
+            &transfer_instruction(
+                &game.token_program,
+                escrow.key,
+                token_account.key,
+                authority.key,
+                refund,
+            )?,
+            &[escrow.clone(), token_account.clone(), authority.clone()],
+            &[&["authority".as_bytes(), &[bump]]],
+        )?;
+    } else if !game.is_casual() {
+        // the stake and the escrow's rent both go back to the creator
+        transfer_lamports(escrow, player_one, escrow.lamports())?;
+    }
+    for (stake, escrow, token_account) in basket_accounts {
+        release_stake(
+            program_id,
+            escrow,
+            token_account,
+            authority,
+            stake,
+            stake.amount,
+        )?;
+    }
+
+    // transfer lamports from game account to user
+    transfer_lamports(game_account, player_one, game_account.lamports())?;
+
+    GameEvent::Cancelled {
+        game_id: game.game_id,
+    }
+    .emit()
+}
+
+fn burn_trophy(program_id: &Pubkey, accounts: &[AccountInfo]) -> ProgramResult {
+    let account_info_iter = &mut accounts.iter();
+    let BurnTrophyAccounts {
+        player_one,
+        game_account,
+    } = BurnTrophyAccounts::from_account_iter(account_info_iter)?;
+
+    // account validation
+    if game_account.owner != program_id {
+        return Err(ProgramError::IllegalOwner);
+    }
+    let game = Game::load(&game_account.data.borrow())?;
+    game.check_version()?;
+    if *player_one.key != game.players[0] {
+        return Err(Error::UnauthorizedToClose.into());
+    }
+    if !matches!(game.state, GameState::Settled { .. }) {
+        return Err(Error::NotATrophy.into());
+    }
+
+    // transfer lamports from game account to user
+    transfer_lamports(game_account, player_one, game_account.lamports())?;
+
+    GameEvent::Burned {
+        game_id: game.game_id,
+    }
+    .emit()
+}
+
+// a game the player takes part in and that is still being played
+fn load_ongoing_game(
+    program_id: &Pubkey,
+    player: &AccountInfo,
+    game_account: &AccountInfo,
+) -> Result<Game, ProgramError> {
+    if game_account.owner != program_id {
+        return Err(ProgramError::IllegalOwner);
+    }
+    let game = Game::load(&game_account.data.borrow())?;
+    game.check_version()?;
+    if !game.players.contains(player.key) {
+        return Err(Error::CanNotPlay.into());
+    }
+    if game.state == GameState::Unaccepted {
+        return Err(Error::UnacceptedGame.into());
+    }
+    if game.state != GameState::Ongoing {
+        return Err(Error::GameAlreadyOver.into());
+    }
+    Ok(game)
+}
+
+fn force_draw(program_id: &Pubkey, accounts: &[AccountInfo]) -> ProgramResult {
+    let account_info_iter = &mut accounts.iter();
+    let PlayerGameAccounts {
+        player,
+        game_account,
+    } = PlayerGameAccounts::from_account_iter(account_info_iter)?;
+
+    // account validation
+    let mut game = load_ongoing_game(program_id, player, game_account)?;
+    let elapsed = Clock::get()?.slot.saturating_sub(game.started_slot);
+    if game.max_duration_slots == 0 || elapsed <= game.max_duration_slots {
+        return Err(Error::DurationNotExceeded.into());
+    }
+
+    // both stakes get refunded on close
+    game.state = GameState::Draw;
+    game.serialize(&mut &mut game_account.data.borrow_mut()[..])
+        .map_err(|_| ProgramError::AccountDataTooSmall)?;
+
+    GameEvent::Drawn {
+        game_id: game.game_id,
+    }
+    .emit()
+}
+
+fn claim_draw(program_id: &Pubkey, accounts: &[AccountInfo]) -> ProgramResult {
+    let account_info_iter = &mut accounts.iter();
+    let PlayerGameAccounts {
+        player,
+        game_account,
+    } = PlayerGameAccounts::from_account_iter(account_info_iter)?;
+
+    // account validation
+    let mut game = load_ongoing_game(program_id, player, game_account)?;
+    if !game.allow_early_draw {
+        return Err(Error::EarlyDrawNotAllowed.into());
+    }
+    if !game.is_dead_draw() {
+        return Err(Error::NotDrawn.into());
+    }
+
+    // both stakes get refunded on close
+    game.state = GameState::Draw;
+    game.serialize(&mut &mut game_account.data.borrow_mut()[..])
+        .map_err(|_| ProgramError::AccountDataTooSmall)?;
+
+    GameEvent::Drawn {
+        game_id: game.game_id,
+    }
+    .emit()
+}
+
+// both players signing is the agreement, no offer is stored on the game
+fn agree_draw(program_id: &Pubkey, accounts: &[AccountInfo]) -> ProgramResult {
+    let account_info_iter = &mut accounts.iter();
+    let AgreeDrawAccounts {
+        player_one,
+        player_two,
+        game_account,
+    } = AgreeDrawAccounts::from_account_iter(account_info_iter)?;
+
+    // account validation
+    if game_account.owner != program_id {
+        return Err(ProgramError::IllegalOwner);
+    }
+    let mut game = Game::load(&game_account.data.borrow())?;
+    game.check_version()?;
+    if *player_one.key != game.players[0] || *player_two.key != game.players[1] {
+        return Err(Error::CanNotPlay.into());
+    }
+    if game.state == GameState::Unaccepted {
+        return Err(Error::UnacceptedGame.into());
+    }
+    if game.state != GameState::Ongoing {
+        return Err(Error::GameAlreadyOver.into());
+    }
+
+    // both stakes get refunded on close
+    game.state = GameState::Draw;
+    game.forfeited = true;
+    game.serialize(&mut &mut game_account.data.borrow_mut()[..])
+        .map_err(|_| ProgramError::AccountDataTooSmall)?;
+
+    GameEvent::Drawn {
+        game_id: game.game_id,
+    }
+    .emit()
+}
+
+fn resign(program_id: &Pubkey, accounts: &[AccountInfo]) -> ProgramResult {
+    let account_info_iter = &mut accounts.iter();
+    let PlayerGameAccounts {
+        player,
+        game_account,
+    } = PlayerGameAccounts::from_account_iter(account_info_iter)?;
+
+    // account validation
+    let mut game = load_ongoing_game(program_id, player, game_account)?;
+
+    // the opponent wins, close_game pays them out as usual. resigning counts as the last move,
+    // which starts a bonded game's settlement window
+    game.state = GameState::Over {
+        winner: game.opponent(player.key),
+    };
+    game.forfeited = true;
+    game.last_move_slot = Clock::get()?.slot;
+    game.serialize(&mut &mut game_account.data.borrow_mut()[..])
+        .map_err(|_| ProgramError::AccountDataTooSmall)?;
+
+    GameEvent::Won {
+        game_id: game.game_id,
+        winner: game.opponent(player.key),
+    }
+    .emit()
+}
+
+fn claim_timeout(program_id: &Pubkey, accounts: &[AccountInfo]) -> ProgramResult {
+    let account_info_iter = &mut accounts.iter();
+    let PlayerGameAccounts {
+        player,
+        game_account,
+    } = PlayerGameAccounts::from_account_iter(account_info_iter)?;
+
+    // account validation
+    let mut game = load_ongoing_game(program_id, player, game_account)?;
+    // only the player waiting on the opponent can claim: whoever moved last, or before the
+    // first move whoever doesn't open
+    let waiting = if game.turns == 0 {
+        game.current_player() != *player.key
+    } else {
+        game.is_last_mover(player.key)
+    };
+    if !waiting {
+        return Err(Error::CanNotPlay.into());
+    }
+    let slot = Clock::get()?.slot;
+    let elapsed = slot.saturating_sub(game.last_move_slot);
+    if game.move_timeout_slots == 0 || elapsed <= game.move_timeout_slots {
+        return Err(Error::TimeoutNotReached.into());
+    }
+
+    // the stalling player forfeits, close_game pays out as usual. the claim counts as the last
+    // move, which starts a bonded game's settlement window
+    game.state = GameState::Over {
+        winner: *player.key,
+    };
+    game.forfeited = true;
+    game.last_move_slot = slot;
+    game.serialize(&mut &mut game_account.data.borrow_mut()[..])
+        .map_err(|_| ProgramError::AccountDataTooSmall)?;
+
+    GameEvent::Won {
+        game_id: game.game_id,
+        winner: *player.key,
+    }
+    .emit()
+}
+
+fn inspect_escrow(program_id: &Pubkey, accounts: &[AccountInfo], mint: Pubkey) -> ProgramResult {
+    let account_info_iter = &mut accounts.iter();
+    let escrow = next_account_info(account_info_iter)?;
+
+    // account validation
+    let (escrow_key, _) =
+        Pubkey::find_program_address(&["escrow".as_bytes(), mint.as_ref()], program_id);
+    if *escrow.key != escrow_key {
+        return Err(ProgramError::InvalidArgument);
+    }
+    let (authority_key, _) = Pubkey::find_program_address(&["authority".as_bytes()], program_id);
+    let escrow_account = unpack_token_account(escrow)?;
+
+    // log what the escrow holds
+    msg!("escrow: {}", escrow.key);
+    msg!(
+        "mint: {} (expected {}, matches: {})",
+        escrow_account.mint,
+        mint,
+        escrow_account.mint == mint
+    );
+    msg!(
+        "owner: {} (expected authority {}, matches: {})",
+        escrow_account.owner,
+        authority_key,
+        escrow_account.owner == authority_key
+    );
+    msg!("balance: {}", escrow_account.amount);
+
+    Ok(())
+}
+
+fn double_or_nothing(program_id: &Pubkey, accounts: &[AccountInfo]) -> ProgramResult {
+    let account_info_iter = &mut accounts.iter();
+    let DoubleOrNothingAccounts {
+        player_one,
+        player_two,
+        game_account,
+        escrow,
+        token_account,
+        token_program,
+    } = DoubleOrNothingAccounts::from_account_iter(account_info_iter)?;
+
+    // account validation
+    if game_account.owner != program_id || !is_token_program(token_account.owner) {
+        return Err(ProgramError::IllegalOwner);
+    }
+    let mut game = Game::load(&game_account.data.borrow())?;
+    game.check_version()?;
+    if *player_one.key != game.players[0] || *player_two.key != game.players[1] {
+        return Err(ProgramError::InvalidArgument);
+    }
+    let winner = match game.state {
+        GameState::Over { winner } => winner,
+        _ => return Err(Error::NotWon.into()),
+    };
+    // the winnings of a bonded game were never escrowed, and a dedicated escrow
+    // is not where the loser's new stake goes, neither is a lamport escrow.
+    // doubling uneven stakes would leave the odds undefined, and a house fee would leave
+    // the winnings short of the doubled stake
+    if game.bond_amount != 0
+        || game.dedicated_escrow
+        || game.is_native()
+        || game.stake_amount_two != game.stake_amount
+        || game.fee_bps != 0
+    {
+        return Err(ProgramError::InvalidArgument);
+    }
+    // the game sticks to the token program it was created with, which has to own the escrow
+    if *token_program.key != game.token_program || escrow.owner != token_program.key {
+        return Err(ProgramError::IncorrectProgramId);
+    }
+    let loser = if winner == *player_one.key {
+        player_two
+    } else {
+        player_one
+    };
+
+    // the winnings stay escrowed as the winner's doubled stake, the loser matches them
+    let stake = Stake {
+        mint: game.stake_mint,
+        amount: game.stake_amount.checked_mul(2).ok_or(Error::Overflow)?,
+    };
+    deposit_stake(program_id, loser, escrow, token_account, &stake)?;
+    game.stake_amount = stake.amount;
+    game.stake_amount_two = stake.amount;
+    // the winnings count as the winner's deposit
+    game.deposits = [stake.amount; 2];
+    for i in 0..game.extra_stake_count as usize {
+        let escrow = next_account_info(account_info_iter)?;
+        let token_account = next_account_info(account_info_iter)?;
+        let stake = &mut game.extra_stakes[i];
+        stake.amount = stake.amount.checked_mul(2).ok_or(Error::Overflow)?;
+        deposit_stake(program_id, loser, escrow, token_account, stake)?;
+    }
+
+    // start over on a fresh board and a fresh series
+    game.wins = [0; 2];
+    game.board = [[None; MAX_BOARD_SIZE]; MAX_BOARD_SIZE];
+    game.move_history = [Game::NO_MOVE; MAX_TILES];
+    game.turns = 0;
+    game.last_mover = Pubkey::default();
+    game.forfeited = false;
+    game.state = GameState::Ongoing;
+    game.started_slot = Clock::get()?.slot;
+    game.last_move_slot = game.started_slot;
+    game.serialize(&mut &mut game_account.data.borrow_mut()[..])
+        .map_err(|_| ProgramError::AccountDataTooSmall)?;
+
+    GameEvent::Restaked {
+        game_id: game.game_id,
+        stake_amount: game.stake_amount,
+    }
+    .emit()
+}
+
+// the previous result is netted against the new stakes: whatever a player is owed beyond
+// the stake is paid out, a shortfall is deposited
+fn rematch(program_id: &Pubkey, accounts: &[AccountInfo]) -> ProgramResult {
+    let account_info_iter = &mut accounts.iter();
+    let RematchAccounts {
+        player_one,
+        player_two,
+        game_account,
+        escrow,
+        authority,
+        token_account_one,
+        token_account_two,
+        token_program,
+    } = RematchAccounts::from_account_iter(account_info_iter)?;
+
+    // account validation
+    if game_account.owner != program_id {
+        return Err(ProgramError::IllegalOwner);
+    }
+    let mut game = Game::load(&game_account.data.borrow())?;
+    game.check_version()?;
+    if *player_one.key != game.players[0] || *player_two.key != game.players[1] {
+        return Err(ProgramError::InvalidArgument);
+    }
+    match game.state {
+        GameState::Unaccepted => return Err(Error::UnacceptedGame.into()),
+        GameState::Ongoing => return Err(Error::OngoingGame.into()),
+        GameState::Settled { .. } => return Err(Error::AlreadySettled.into()),
+        GameState::Cancelled => return Err(Error::UnclosableGame.into()),
+        GameState::Over { .. } | GameState::Draw => {}
+    }
+    // only a plain stake in the shared escrow carries over
+    if game.bond_amount != 0
+        || game.dedicated_escrow
+        || game.is_native()
+        || game.extra_stake_count != 0
+    {
+        return Err(ProgramError::InvalidArgument);
+    }
+    // the game sticks to the token program it was created with, which has to own the escrow
+    if *token_program.key != game.token_program || escrow.owner != token_program.key {
+        return Err(ProgramError::IncorrectProgramId);
+    }
+    verify_pdas(program_id, &game.stake_mint, None, escrow, authority)?;
+    let stake = Stake {
+        mint: game.stake_mint,
+        amount: game.stake_amount,
+    };
+    let stakes = game.stakes();
+
+    // what the previous game owes each player, the fee is paid out right away
+    let mut owed = [0u64; 2];
+    for (recipient, amount) in game.payouts() {
+        match game.players.iter().position(|player| *player == recipient) {
+            Some(i) => owed[i] = owed[i].checked_add(amount).ok_or(Error::Overflow)?,
+            None => {
+                let fee_account = next_account_info(account_info_iter)?;
+                let receive_account = unpack_payout_account(fee_account, &stake.mint)?;
+                if receive_account.owner != recipient {
+                    return Err(ProgramError::InvalidArgument);
+                }
+                release_stake(program_id, escrow, fee_account, authority, &stake, amount)?;
+            }
+        }
+    }
+    let players = [player_one, player_two];
+    let token_accounts = [token_account_one, token_account_two];
+    for i in 0..2 {
+        let receive_account = unpack_payout_account(token_accounts[i], &stake.mint)?;
+        if receive_account.owner != game.players[i] {
+            return Err(ProgramError::InvalidArgument);
+        }
+        if owed[i] > stakes[i] {
+            let amount = owed[i] - stakes[i];
+            release_stake(
+                program_id,
+                escrow,
+                token_accounts[i],
+                authority,
+                &stake,
+                amount,
+            )?;
+        } else if owed[i] < stakes[i] {
+            let shortfall = Stake {
+                mint: stake.mint,
+                amount: stakes[i] - owed[i],
+            };
+            deposit_stake(
+                program_id,
+                players[i],
+                escrow,
+                token_accounts[i],
+                &shortfall,
+            )?;
+        }
+    }
+
+    // start over on a fresh board and a fresh series, the other player opens this time
+    game.deposits = stakes;
+    game.wins = [0; 2];
+    game.reset_board();
+    game.started_slot = Clock::get()?.slot;
+    game.last_move_slot = game.started_slot;
+    game.serialize(&mut &mut game_account.data.borrow_mut()[..])
+        .map_err(|_| ProgramError::AccountDataTooSmall)?;
+
+    GameEvent::Accepted(game.snapshot()).emit()
+}
+
+// update the global stats
+fn count_settled_game(stats: &AccountInfo) -> ProgramResult {
+    let mut global_stats = GlobalStats::load(&stats.data.borrow())?;
+    if !global_stats.is_initialized() {
+        return Err(ProgramError::UninitializedAccount);
+    }
+    global_stats.games_settled = global_stats
+        .games_settled
+        .checked_add(1)
+        .ok_or(Error::Overflow)?;
+    global_stats
+        .serialize(&mut &mut stats.data.borrow_mut()[..])
+        .map_err(|_| ProgramError::AccountDataTooSmall)?;
+    Ok(())
+}
+
+// a loser who won't pay up forfeits their bond, the winner takes both bonds instead.
+// bond_amount never exceeds the stake, so slashing never pays more than settling
+fn slash_bond(program_id: &Pubkey, accounts: &[AccountInfo]) -> ProgramResult {
+    let account_info_iter = &mut accounts.iter();
+    let SlashBondAccounts {
+        winner,
+        game_account,
+        escrow,
+        authority,
+        token_account,
+        token_program,
+        stats,
+    } = SlashBondAccounts::from_account_iter(account_info_iter)?;
+
+    // account validation
+    if game_account.owner != program_id || stats.owner != program_id {
+        return Err(ProgramError::IllegalOwner);
+    }
+    let (stats_key, _) = Pubkey::find_program_address(&["stats".as_bytes()], program_id);
+    if *stats.key != stats_key {
+        return Err(ProgramError::InvalidArgument);
+    }
+    let mut game = Game::load(&game_account.data.borrow())?;
+    game.check_version()?;
+    if game.bond_amount == 0 {
+        return Err(Error::NotBonded.into());
+    }
+    match game.state {
+        GameState::Over { winner: key } if key == *winner.key => {}
+        GameState::Over { .. } => return Err(ProgramError::InvalidArgument),
+        _ => return Err(Error::NotWon.into()),
+    }
+    // the game's last move ended it, the loser gets the window to settle honestly
+    let elapsed = Clock::get()?.slot.saturating_sub(game.last_move_slot);
+    if elapsed <= SETTLEMENT_WINDOW_SLOTS {
+        return Err(Error::SettlementWindowOpen.into());
+    }
+    // the game sticks to the token program it was created with, which has to own the escrow
+    if *token_program.key != game.token_program || escrow.owner != token_program.key {
+        return Err(ProgramError::IncorrectProgramId);
+    }
+    let (_, bump) = verify_pdas(
+        program_id,
+        &game.stake_mint,
+        game.dedicated_escrow.then_some(game_account.key),
+        escrow,
+        authority,
+    )?;
+    let receive_account = unpack_payout_account(token_account, &game.stake_mint)?;
+    if receive_account.owner != *winner.key {
+        return Err(ProgramError::InvalidArgument);
+    }
+
+    // hand both bonds to the winner
+    let amount = game.bond_amount.checked_mul(2).ok_or(Error::Overflow)?;
+    invoke_signed(
+        &transfer_instruction(
+            &game.token_program,
+            escrow.key,
+            token_account.key,
+            authority.key,
+            amount,
+        )?,
+        &[escrow.clone(), token_account.clone(), authority.clone()],
+        &[&["authority".as_bytes(), &[bump]]],
+    )?;
+
+    // the account is kept as a trophy, burn_trophy reclaims the rent
+    game.state = GameState::Settled {
+        winner: Some(*winner.key),
+    };
+    game.serialize(&mut &mut game_account.data.borrow_mut()[..])
+        .map_err(|_| ProgramError::AccountDataTooSmall)?;
+    count_settled_game(stats)?;
+
+    GameEvent::Slashed {
+        game_id: game.game_id,
+        winner: *winner.key,
+        amount,
+    }
+    .emit()
+}
+
+// like log_board and verify_winning_move this only reads the game, so a legacy layout is fine
+fn get_legal_moves(program_id: &Pubkey, accounts: &[AccountInfo]) -> ProgramResult {
+    let account_info_iter = &mut accounts.iter();
+    let game_account = next_account_info(account_info_iter)?;
+
+    // account validation
+    if game_account.owner != program_id {
+        return Err(ProgramError::IllegalOwner);
+    }
+    let game = Game::load(&game_account.data.borrow())?;
+
+    // return the moves as a borsh encoded Vec<Move>
+    set_return_data(&game.legal_moves().try_to_vec()?);
+
+    Ok(())
+}
+
+// a readable snapshot for explorers and debugging, the account is only read
+fn log_board(program_id: &Pubkey, accounts: &[AccountInfo]) -> ProgramResult {
+    let account_info_iter = &mut accounts.iter();
+    let game_account = next_account_info(account_info_iter)?;
+
+    // account validation
+    if game_account.owner != program_id {
+        return Err(ProgramError::IllegalOwner);
+    }
+    let game = Game::load(&game_account.data.borrow())?;
+
+    // log the board row by row, then the turn and the state
+    for row in game.board_rows() {
+        msg!("{}", row);
+    }
+    let symbol = match game.current_symbol() {
+        Symbol::X => "X",
+        Symbol::O => "O",
+    };
+    match game.current_turn_player() {
+        Some(player) => msg!("turn: {} ({})", player, symbol),
+        None => msg!("turn: none"),
+    }
+    match game.state {
+        GameState::Unaccepted => msg!("state: unaccepted"),
+        GameState::Ongoing => msg!("state: ongoing"),
+        GameState::Over { winner } => msg!("state: over, winner: {}", winner),
+        GameState::Draw => msg!("state: draw"),
+        GameState::Settled {
+            winner: Some(winner),
+        } => msg!("state: settled, winner: {}", winner),
+        GameState::Settled { winner: None } => msg!("state: settled, draw"),
+        GameState::Cancelled => msg!("state: cancelled"),
+    }
+
+    Ok(())
+}
+
+// an empty escrow has no game left depending on it, whoever closes it takes the rent and
+// the next game staking the mint pays it again
+fn close_escrow(
+    program_id: &Pubkey,
+    accounts: &[AccountInfo],
+    mint: Pubkey,
+    game: Option<Pubkey>,
+) -> ProgramResult {
+    let account_info_iter = &mut accounts.iter();
+    let recipient = next_account_info(account_info_iter)?;
+    let escrow = next_account_info(account_info_iter)?;
+    let authority = next_account_info(account_info_iter)?;
+    let token_program = next_account_info(account_info_iter)?;
+
+    // account validation
+    if !recipient.is_signer {
+        return Err(ProgramError::MissingRequiredSignature);
+    }
+    if !is_token_program(token_program.key) || escrow.owner != token_program.key {
+        return Err(ProgramError::IncorrectProgramId);
+    }
+    let (_, bump) = verify_pdas(program_id, &mint, game.as_ref(), escrow, authority)?;
+    if unpack_token_account(escrow)?.amount != 0 {
+        return Err(Error::EscrowNotEmpty.into());
+    }
+
+    // close the token account, its rent goes to the recipient
+    invoke_signed(
+        &instruction::close_account(
+            token_program.key,
+            escrow.key,
+            recipient.key,
+            authority.key,
+            &[],
+        )?,
+        &[escrow.clone(), recipient.clone(), authority.clone()],
+        &[&["authority".as_bytes(), &[bump]]],
+    )
+}
+
+fn verify_winning_move(program_id: &Pubkey, accounts: &[AccountInfo], play: Move) -> ProgramResult {
+    let account_info_iter = &mut accounts.iter();
+    let game_account = next_account_info(account_info_iter)?;
+
+    // account validation
+    if game_account.owner != program_id {
+        return Err(ProgramError::IllegalOwner);
+    }
+    let game = Game::load(&game_account.data.borrow())?;
+
+    // play the move on a copy, nothing is written back
+    let state = game.preview_move(play)?;
+    match state {
+        GameState::Over { winner } => msg!("the move ends the game, winner: {}", winner),
+        GameState::Draw => msg!("the move ends the game in a draw"),
+        _ => msg!("the move does not end the game"),
+    }
+    // return the resulting state borsh encoded
+    set_return_data(&state.try_to_vec()?);
+
+    Ok(())
+}
+
+fn create_escrow_if_needed<'a>(
+    program_id: &Pubkey,
+    payer: &AccountInfo<'a>,
+    mint: &AccountInfo<'a>,
+    escrow: &AccountInfo<'a>,
+) -> ProgramResult {
+    let (escrow_key, bump) =
+        Pubkey::find_program_address(&["escrow".as_bytes(), mint.key.as_ref()], program_id);
+    if *escrow.key != escrow_key {
+        return Err(ProgramError::InvalidArgument);
+    }
+    if !escrow.data_is_empty() {
+        return Ok(());
+    }
+    let escrow_len = escrow_len(mint)?;
+    let rent_amount = Rent::get()?.minimum_balance(escrow_len);
+    let (authority, _) = Pubkey::find_program_address(&["authority".as_bytes()], program_id);
+    invoke_signed(
+        &system_instruction::create_account(
+            payer.key,
+            escrow.key,
+            rent_amount,
+            escrow_len.try_into().unwrap(),
+            mint.owner,
+        ),
+        &[payer.clone(), escrow.clone()],
+        &[&["escrow".as_bytes(), mint.key.as_ref(), &[bump]]],
+    )?;
+    invoke(
+        &instruction::initialize_account3(mint.owner, escrow.key, mint.key, &authority)?,
+        &[escrow.clone(), mint.clone()],
+    )
+}
+
+fn deposit_stake<'a>(
+    program_id: &Pubkey,
+    player: &AccountInfo<'a>,
+    escrow: &AccountInfo<'a>,
+    token_account: &AccountInfo<'a>,
+    stake: &Stake,
+) -> ProgramResult {
+    let (escrow_key, _) =
+        Pubkey::find_program_address(&["escrow".as_bytes(), stake.mint.as_ref()], program_id);
+    if *escrow.key != escrow_key {
+        return Err(ProgramError::InvalidArgument);
+    }
+    check_token_account_key(token_account)?;
+    // the escrow was created by the token program of the mint
+    if token_account.owner != escrow.owner {
+        return Err(ProgramError::IllegalOwner);
+    }
+    let send_account = unpack_token_account(token_account)?;
+    if send_account.mint != stake.mint || send_account.owner != *player.key {
+        return Err(ProgramError::InvalidArgument);
+    }
+    if send_account.amount < stake.amount {
+        return Err(ProgramError::InsufficientFunds);
+    }
+    invoke(
+        &transfer_instruction(
+            escrow.owner,
+            token_account.key,
+            escrow.key,
+            player.key,
+            stake.amount,
+        )?,
+        &[token_account.clone(), escrow.clone(), player.clone()],
+    )
+}
+
+fn release_stake<'a>(
+    program_id: &Pubkey,
+    escrow: &AccountInfo<'a>,
+    token_account: &AccountInfo<'a>,
+    authority: &AccountInfo<'a>,
+    stake: &Stake,
+    amount: u64,
+) -> ProgramResult {
+    let (_, bump) = verify_pdas(program_id, &stake.mint, None, escrow, authority)?;
+    if unpack_token_account(escrow)?.amount < amount {
+        return Err(ProgramError::InsufficientFunds);
+    }
+    invoke_signed(
+        &transfer_instruction(
+            escrow.owner,
+            escrow.key,
+            token_account.key,
+            authority.key,
+            amount,
+        )?,
+        &[escrow.clone(), token_account.clone(), authority.clone()],
+        &[&["authority".as_bytes(), &[bump]]],
+    )
+}
+
+// creates the winner's associated token account for the mint if it doesn't exist yet,
+// the extra accounts are only expected when it has to be created
+fn create_winner_ata_if_missing<'a>(
+    account_info_iter: &mut Iter<AccountInfo<'a>>,
+    payer: &AccountInfo<'a>,
+    token_account: &AccountInfo<'a>,
+    winner: &Pubkey,
+    mint_key: &Pubkey,
+    token_program: &AccountInfo<'a>,
+    system_program: &AccountInfo<'a>,
+) -> ProgramResult {
+    if !token_account.data_is_empty() {
+        return Ok(());
+    }
+    let mint = next_account_info(account_info_iter)?;
+    let winner_account = next_account_info(account_info_iter)?;
+    let associated_token_program = next_account_info(account_info_iter)?;
+    if *associated_token_program.key != spl_associated_token_account::ID {
+        return Err(ProgramError::IncorrectProgramId);
+    }
+    if *mint.key != *mint_key || *winner_account.key != *winner {
+        return Err(ProgramError::InvalidArgument);
+    }
+    if mint.owner != token_program.key {
+        return Err(ProgramError::IncorrectProgramId);
+    }
+    let ata = get_associated_token_address_with_program_id(winner, mint_key, token_program.key);
+    if *token_account.key != ata {
+        return Err(ProgramError::InvalidArgument);
+    }
+    if !payer.is_signer {
+        return Err(ProgramError::MissingRequiredSignature);
+    }
+    invoke(
+        &create_associated_token_account(payer.key, winner, mint_key, token_program.key),
+        &[
+            payer.clone(),
+            token_account.clone(),
+            winner_account.clone(),
+            mint.clone(),
+            system_program.clone(),
+            token_program.clone(),
+            associated_token_program.clone(),
+        ],
+    )
+}
+
+// a game account derived from its players and a nonce picked by the creator
+pub fn find_game_address(
+    program_id: &Pubkey,
+    player_one: &Pubkey,
+    player_two: &Pubkey,
+    nonce: u64,
+) -> (Pubkey, u8) {
+    Pubkey::find_program_address(
+        &[
+            "game".as_bytes(),
+            player_one.as_ref(),
+            player_two.as_ref(),
+            &nonce.to_le_bytes(),
+        ],
+        program_id,
+    )
+}
+
+// escrows are shared by every game staking the mint, unless a game is given for a
+// dedicated escrow
+pub fn find_escrow_address(
+    program_id: &Pubkey,
+    mint: &Pubkey,
+    game: Option<&Pubkey>,
+) -> (Pubkey, u8) {
+    match game {
+        Some(game) => Pubkey::find_program_address(
+            &["escrow".as_bytes(), mint.as_ref(), game.as_ref()],
+            program_id,
+        ),
+        None => Pubkey::find_program_address(&["escrow".as_bytes(), mint.as_ref()], program_id),
+    }
+}
+
+// a native game keeps its lamports in an escrow of its own, owned by the program
+pub fn find_native_escrow_address(program_id: &Pubkey, game: &Pubkey) -> (Pubkey, u8) {
+    Pubkey::find_program_address(&["sol_escrow".as_bytes(), game.as_ref()], program_id)
+}
+
+fn verify_native_escrow(program_id: &Pubkey, game: &Pubkey, escrow: &AccountInfo) -> ProgramResult {
+    let (escrow_key, _) = find_native_escrow_address(program_id, game);
+    if *escrow.key != escrow_key {
+        return Err(ProgramError::InvalidArgument);
+    }
+    if escrow.owner != program_id {
+        return Err(ProgramError::IllegalOwner);
+    }
+    Ok(())
+}
+
+// checks the escrow of the given mint and the escrow authority, returning their bumps.
+// the trust chain: the game's stake mint derives the escrow address, and the escrow's own
+// token data has to agree on that mint and be controlled by the authority pda, so a
+// tampered stake_mint can't point the payout at some other token account
+fn verify_pdas(
+    program_id: &Pubkey,
+    mint: &Pubkey,
+    game: Option<&Pubkey>,
+    escrow: &AccountInfo,
+    authority: &AccountInfo,
+) -> Result<(u8, u8), ProgramError> {
+    let (escrow_key, escrow_bump) = find_escrow_address(program_id, mint, game);
+    if *escrow.key != escrow_key {
+        return Err(ProgramError::InvalidArgument);
+    }
+    let (authority_key, authority_bump) =
+        Pubkey::find_program_address(&["authority".as_bytes()], program_id);
+    if *authority.key != authority_key {
+        return Err(ProgramError::InvalidArgument);
+    }
+    let escrow_account = unpack_token_account(escrow)?;
+    if escrow_account.mint != *mint || escrow_account.owner != authority_key {
+        return Err(ProgramError::InvalidAccountData);
+    }
+    Ok((escrow_bump, authority_bump))
+}
+
+fn unpack_payout_account(
+    token_account: &AccountInfo,
+    mint: &Pubkey,
+) -> Result<Account, ProgramError> {
+    check_token_account_key(token_account)?;
+    let receive_account = unpack_token_account(token_account)?;
+    if receive_account.mint != *mint {
+        return Err(ProgramError::InvalidArgument);
+    }
+    Ok(receive_account)
+}
+
+// a token account of either token program, token-2022 ones may carry extensions
+fn unpack_token_account(token_account: &AccountInfo) -> Result<Account, ProgramError> {
+    if !is_token_program(token_account.owner) {
+        return Err(ProgramError::IllegalOwner);
+    }
+    let data = token_account.data.borrow();
+    Ok(StateWithExtensions::<Account>::unpack(&data)?.base)
+}
+
+// an escrow of the mint needs room for the account extensions the mint requires
+fn escrow_len(mint: &AccountInfo) -> Result<usize, ProgramError> {
+    let data = mint.data.borrow();
+    let mint_extensions = StateWithExtensions::<Mint>::unpack(&data)?.get_extension_types()?;
+    let account_extensions = ExtensionType::get_required_init_account_extensions(&mint_extensions);
+    Ok(ExtensionType::get_account_len::<Account>(
+        &account_extensions,
+    ))
+}
+
+// the unchecked transfer behaves the same under both token programs, a token-2022 mint
+// that needs transfer_checked (a transfer fee for one) makes the cpi fail instead
+#[allow(deprecated)]
+fn transfer_instruction(
+    token_program: &Pubkey,
+    source: &Pubkey,
+    destination: &Pubkey,
+    authority: &Pubkey,
+    amount: u64,
+) -> Result<solana_program::instruction::Instruction, ProgramError> {
+    instruction::transfer(token_program, source, destination, authority, &[], amount)
+}
+
+// moving more than the source holds is reported as such rather than as a custom overflow,
+// ProgramError::ArithmeticOverflow only exists from solana-program 1.17 on
+fn transfer_lamports(
+    source: &AccountInfo,
+    destination: &AccountInfo,
+    amount: u64,
+) -> ProgramResult {
+    let source_balance = source
+        .lamports()
+        .checked_sub(amount)
+        .ok_or(ProgramError::InsufficientFunds)?;
+    let destination_balance = destination
+        .lamports()
+        .checked_add(amount)
+        .ok_or(Error::Overflow)?;
+    **source.try_borrow_mut_lamports()? = source_balance;
+    **destination.try_borrow_mut_lamports()? = destination_balance;
+
     Ok(())
 }
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+
+    use crate::instruction::tests::{accept, create_game};
+    use crate::state::tests::{play_all, setup};
+    use borsh::BorshDeserialize;
+    use solana_program::entrypoint::SUCCESS;
+    use solana_program::program_pack::Pack;
+    use solana_program::program_stubs::{set_syscall_stubs, SyscallStubs};
+    use std::cell::{Cell, RefCell};
+
+    // the slot every handler sees as the current one
+    pub(crate) const NOW_SLOT: u64 = 1_000_000;
+
+    // what the stubbed cpis fail with
+    pub(crate) const CPI_ERROR: ProgramError = ProgramError::Custom(u32::MAX);
+
+    thread_local! {
+        static RETURN_DATA: RefCell<Vec<u8>> = const { RefCell::new(Vec::new()) };
+        static FAIL_CPIS: Cell<bool> = const { Cell::new(false) };
+        static LOGS: RefCell<Vec<String>> = const { RefCell::new(Vec::new()) };
+        static CPIS: RefCell<Vec<solana_program::instruction::Instruction>> =
+            const { RefCell::new(Vec::new()) };
+    }
+
+    struct Stubs;
+
+    impl SyscallStubs for Stubs {
+        fn sol_get_clock_sysvar(&self, var_addr: *mut u8) -> u64 {
+            let clock = Clock {
+                slot: NOW_SLOT,
+                ..Clock::default()
+            };
+            unsafe { *(var_addr as *mut Clock) = clock };
+            SUCCESS
+        }
+
+        fn sol_get_rent_sysvar(&self, var_addr: *mut u8) -> u64 {
+            unsafe { *(var_addr as *mut Rent) = Rent::default() };
+            SUCCESS
+        }
+
+        fn sol_log(&self, message: &str) {
+            LOGS.with(|logs| logs.borrow_mut().push(message.to_string()));
+        }
+
+        fn sol_set_return_data(&self, data: &[u8]) {
+            RETURN_DATA.with(|return_data| *return_data.borrow_mut() = data.to_vec());
+        }
+
+        // a cpi is only recorded, or fails when the test asked for it
+        fn sol_invoke_signed(
+            &self,
+            instruction: &solana_program::instruction::Instruction,
+            _account_infos: &[AccountInfo],
+            _signers_seeds: &[&[&[u8]]],
+        ) -> ProgramResult {
+            if FAIL_CPIS.with(Cell::get) {
+                return Err(CPI_ERROR);
+            }
+            CPIS.with(|cpis| cpis.borrow_mut().push(instruction.clone()));
+            Ok(())
+        }
+    }
+
+    // the clock and the return data of the calling test's thread
+    pub(crate) fn install_stubs() {
+        set_syscall_stubs(Box::new(Stubs));
+    }
+
+    pub(crate) fn fail_cpis(fail: bool) {
+        FAIL_CPIS.with(|fail_cpis| fail_cpis.set(fail));
+    }
+
+    // the messages logged since the last call
+    pub(crate) fn take_logs() -> Vec<String> {
+        LOGS.with(|logs| logs.take())
+    }
+
+    // the cpis made since the last call
+    pub(crate) fn take_cpis() -> Vec<solana_program::instruction::Instruction> {
+        CPIS.with(|cpis| cpis.take())
+    }
+
+    pub(crate) fn return_data() -> Vec<u8> {
+        RETURN_DATA.with(|return_data| return_data.borrow().clone())
+    }
+
+    // an account to call the processor with, the account infos borrow from it
+    pub(crate) struct TestAccount {
+        pub key: Pubkey,
+        pub signer: bool,
+        pub lamports: u64,
+        pub data: Vec<u8>,
+        pub owner: Pubkey,
+    }
+
+    impl TestAccount {
+        pub fn new(key: Pubkey, signer: bool) -> Self {
+            Self {
+                key,
+                signer,
+                lamports: 0,
+                data: Vec::new(),
+                owner: SYSTEM_PROGRAM_ID,
+            }
+        }
+
+        pub fn owned_by(mut self, owner: Pubkey) -> Self {
+            self.owner = owner;
+            self
+        }
+
+        // a game account of the program holding game
+        pub fn game(program_id: &Pubkey, game: &Game) -> Self {
+            let mut data = vec![0; Game::LEN];
+            game.serialize(&mut &mut data[..]).unwrap();
+            Self {
+                data,
+                ..Self::new(Pubkey::new_unique(), false).owned_by(*program_id)
+            }
+        }
+
+        // an initialized token account, owned by the legacy token program
+        pub fn token(key: Pubkey, mint: Pubkey, owner: Pubkey, amount: u64) -> Self {
+            let mut data = vec![0; Account::LEN];
+            Account::pack(
+                Account {
+                    mint,
+                    owner,
+                    amount,
+                    state: spl_token_2022::state::AccountState::Initialized,
+                    ..Account::default()
+                },
+                &mut data,
+            )
+            .unwrap();
+            Self {
+                data,
+                ..Self::new(key, false).owned_by(spl_token::ID)
+            }
+        }
+
+        pub fn info(&mut self) -> AccountInfo<'_> {
+            AccountInfo::new(
+                &self.key,
+                self.signer,
+                true,
+                &mut self.lamports,
+                &mut self.data,
+                &self.owner,
+                false,
+                0,
+            )
+        }
+    }
+
+    pub(crate) fn process(
+        program_id: &Pubkey,
+        accounts: &mut [TestAccount],
+        instruction: Instruction,
+    ) -> ProgramResult {
+        let infos: Vec<AccountInfo> = accounts.iter_mut().map(TestAccount::info).collect();
+        process_instruction(program_id, &infos, &instruction.pack())
+    }
+
+    // a game waiting on player_two, staked in the legacy token program
+    fn unaccepted(player_two: Pubkey) -> Game {
+        let mut game = setup();
+        game.state = GameState::Unaccepted;
+        game.players[1] = player_two;
+        game.deposits = [100, 0];
+        game.token_program = spl_token::ID;
+        game
+    }
+
+    // the accounts of a CreateGame by player staking mint, the cpis are stubbed so the game
+    // and stats accounts already have their size
+    fn create_accounts(program_id: &Pubkey, player: Pubkey, mint: Pubkey) -> [TestAccount; 8] {
+        let (escrow, _) = find_escrow_address(program_id, &mint, None);
+        let (authority, _) = Pubkey::find_program_address(&["authority".as_bytes()], program_id);
+        let (stats, _) = Pubkey::find_program_address(&["stats".as_bytes()], program_id);
+        let mut game_account = TestAccount::new(Pubkey::new_unique(), true).owned_by(*program_id);
+        game_account.data = vec![0; Game::LEN];
+        let mut stats = TestAccount::new(stats, false).owned_by(*program_id);
+        stats.data = vec![0; GlobalStats::LEN];
+        [
+            TestAccount::new(player, true),
+            game_account,
+            TestAccount::new(mint, false).owned_by(spl_token::ID),
+            TestAccount::token(escrow, mint, authority, 0),
+            TestAccount::token(Pubkey::new_unique(), mint, player, 100),
+            TestAccount::new(spl_token::ID, false),
+            TestAccount::new(SYSTEM_PROGRAM_ID, false),
+            stats,
+        ]
+    }
+
+    // the accounts of an AcceptGame by player_two, who holds enough to match the stake
+    fn accept_accounts(program_id: &Pubkey, game: &Game, player_two: Pubkey) -> [TestAccount; 5] {
+        let (escrow, _) = find_escrow_address(program_id, &game.stake_mint, None);
+        let (authority, _) = Pubkey::find_program_address(&["authority".as_bytes()], program_id);
+        [
+            TestAccount::new(player_two, true),
+            TestAccount::game(program_id, game),
+            TestAccount::token(escrow, game.stake_mint, authority, 100),
+            TestAccount::token(Pubkey::new_unique(), game.stake_mint, player_two, 100),
+            TestAccount::new(spl_token::ID, false),
+        ]
+    }
+
+    #[test]
+    fn anyone_can_accept_an_open_game_without_a_secret() {
+        install_stubs();
+        let program_id = Pubkey::new_unique();
+        let game = unaccepted(Pubkey::default());
+        let third_party = Pubkey::new_unique();
+        let mut accounts = accept_accounts(&program_id, &game, third_party);
+        process(&program_id, &mut accounts, accept(0, None)).unwrap();
+        let accepted = Game::load(&accounts[1].data).unwrap();
+        assert_eq!(accepted.players, [game.players[0], third_party]);
+        assert_eq!(accepted.state, GameState::Ongoing);
+    }
+
+    #[test]
+    fn a_targeted_game_rejects_any_other_signer() {
+        install_stubs();
+        let program_id = Pubkey::new_unique();
+        let game = unaccepted(Pubkey::new_unique());
+        let mut accounts = accept_accounts(&program_id, &game, Pubkey::new_unique());
+        assert_eq!(
+            process(&program_id, &mut accounts, accept(0, None),),
+            Err(Error::UnauthorizedToAccept.into())
+        );
+    }
+
+    #[test]
+    fn accepting_needs_the_fee_the_accepter_agreed_to() {
+        install_stubs();
+        let program_id = Pubkey::new_unique();
+        let player_two = Pubkey::new_unique();
+        let mut game = unaccepted(player_two);
+        game.fee_bps = 250;
+        game.fee_collector = Pubkey::new_unique();
+        let pinned = |fee_bps, fee_collector| Instruction::AcceptGame {
+            fee_bps,
+            fee_collector,
+            join_secret: None,
+        };
+        for instruction in [
+            accept(0, None),
+            pinned(250, Pubkey::new_unique()),
+            pinned(100, game.fee_collector),
+        ] {
+            let mut accounts = accept_accounts(&program_id, &game, player_two);
+            assert_eq!(
+                process(&program_id, &mut accounts, instruction),
+                Err(Error::FeeMismatch.into())
+            );
+        }
+        let mut accounts = accept_accounts(&program_id, &game, player_two);
+        process(&program_id, &mut accounts, pinned(250, game.fee_collector)).unwrap();
+    }
+
+    #[test]
+    fn double_or_nothing_rejects_a_game_with_a_house_fee() {
+        let program_id = Pubkey::new_unique();
+        let mut game = setup();
+        game.fee_bps = 250;
+        game.fee_collector = Pubkey::new_unique();
+        play_all(&mut game, &[(0, 0), (1, 0), (0, 1), (1, 1), (0, 2)]);
+        let mut accounts = [
+            TestAccount::new(game.players[0], true),
+            TestAccount::new(game.players[1], true),
+            TestAccount::game(&program_id, &game),
+            TestAccount::new(Pubkey::new_unique(), false),
+            TestAccount::new(Pubkey::new_unique(), false).owned_by(spl_token::ID),
+            TestAccount::new(spl_token::ID, false),
+        ];
+        assert_eq!(
+            process(&program_id, &mut accounts, Instruction::DoubleOrNothing),
+            Err(ProgramError::InvalidArgument)
+        );
+    }
+
+    #[test]
+    fn slash_bond_waits_for_the_settlement_window() {
+        install_stubs();
+        let program_id = Pubkey::new_unique();
+        let mut game = setup();
+        game.bond_amount = 10;
+        game.token_program = spl_token::ID;
+        play_all(&mut game, &[(0, 0), (1, 0), (0, 1), (1, 1), (0, 2)]);
+        game.last_move_slot = NOW_SLOT - SETTLEMENT_WINDOW_SLOTS;
+        let (stats, _) = Pubkey::find_program_address(&["stats".as_bytes()], &program_id);
+        let accounts = |game: &Game| {
+            [
+                TestAccount::new(game.players[0], true),
+                TestAccount::game(&program_id, game),
+                TestAccount::new(Pubkey::new_unique(), false),
+                TestAccount::new(Pubkey::new_unique(), false),
+                TestAccount::new(Pubkey::new_unique(), false),
+                TestAccount::new(spl_token::ID, false),
+                TestAccount::new(stats, false).owned_by(program_id),
+            ]
+        };
+        assert_eq!(
+            process(&program_id, &mut accounts(&game), Instruction::SlashBond),
+            Err(Error::SettlementWindowOpen.into())
+        );
+        // once the window closed the escrow accounts are checked next
+        game.last_move_slot -= 1;
+        assert_eq!(
+            process(&program_id, &mut accounts(&game), Instruction::SlashBond),
+            Err(ProgramError::IncorrectProgramId)
+        );
+    }
+
+    #[test]
+    fn settled_games_are_counted_on_valid_stats_only() {
+        let stats = GlobalStats {
+            games_created: 3,
+            games_settled: 1,
+            total_staked: 600,
+            is_initialized: true,
+        };
+        let mut account = TestAccount::new(Pubkey::new_unique(), false);
+        account.data = stats.try_to_vec().unwrap();
+        count_settled_game(&account.info()).unwrap();
+        let counted = GlobalStats::load(&account.data).unwrap();
+        assert_eq!((counted.games_created, counted.games_settled), (3, 2));
+
+        account.data = vec![0; GlobalStats::LEN];
+        assert_eq!(
+            count_settled_game(&account.info()),
+            Err(ProgramError::UninitializedAccount)
+        );
+        account.data = vec![0; GlobalStats::LEN - 1];
+        assert_eq!(
+            count_settled_game(&account.info()),
+            Err(ProgramError::InvalidAccountData)
+        );
+    }
+
+    #[test]
+    fn transfer_lamports_can_empty_the_source_exactly() {
+        let (source_key, destination_key, owner) = (
+            Pubkey::new_unique(),
+            Pubkey::new_unique(),
+            Pubkey::new_unique(),
+        );
+        let (mut source_lamports, mut destination_lamports) = (1_000, 5);
+        let (mut source_data, mut destination_data) = (Vec::new(), Vec::new());
+        let source = AccountInfo::new(
+            &source_key,
+            false,
+            true,
+            &mut source_lamports,
+            &mut source_data,
+            &owner,
+            false,
+            0,
+        );
+        let destination = AccountInfo::new(
+            &destination_key,
+            false,
+            true,
+            &mut destination_lamports,
+            &mut destination_data,
+            &owner,
+            false,
+            0,
+        );
+        transfer_lamports(&source, &destination, 1_000).unwrap();
+        assert_eq!((source.lamports(), destination.lamports()), (0, 1_005));
+        assert_eq!(
+            transfer_lamports(&source, &destination, 1),
+            Err(ProgramError::InsufficientFunds)
+        );
+        assert_eq!((source.lamports(), destination.lamports()), (0, 1_005));
+    }
+
+    #[test]
+    fn a_stranger_gets_can_not_play() {
+        install_stubs();
+        let program_id = Pubkey::new_unique();
+        let game = setup();
+        let mut accounts = [
+            TestAccount::new(Pubkey::new_unique(), true),
+            TestAccount::game(&program_id, &game),
+        ];
+        assert_eq!(
+            process(
+                &program_id,
+                &mut accounts,
+                Instruction::PlayGame(Move { row: 0, col: 0 })
+            ),
+            Err(ProgramError::Custom(Error::CanNotPlay as u32))
+        );
+        assert_eq!(Error::CanNotPlay as u32, 6);
+    }
+
+    #[test]
+    fn the_creator_can_not_accept_their_own_game() {
+        install_stubs();
+        let program_id = Pubkey::new_unique();
+        for player_two in [Pubkey::default(), Pubkey::new_unique()] {
+            let game = unaccepted(player_two);
+            let mut accounts = accept_accounts(&program_id, &game, game.players[0]);
+            assert_eq!(
+                process(&program_id, &mut accounts, accept(0, None)),
+                Err(ProgramError::Custom(Error::UnauthorizedToAccept as u32))
+            );
+        }
+    }
+
+    #[test]
+    fn create_game_checks_who_owns_the_mint_and_token_account() {
+        install_stubs();
+        let program_id = Pubkey::new_unique();
+        let player = Pubkey::new_unique();
+        let mint = Pubkey::new_unique();
+        let mut accounts = create_accounts(&program_id, player, mint);
+        process(&program_id, &mut accounts, create_game()).unwrap();
+        let game = Game::load(&accounts[1].data).unwrap();
+        assert_eq!(game.players[0], player);
+        assert_eq!(game.state, GameState::Unaccepted);
+        assert_eq!((game.stake_mint, game.token_program), (mint, spl_token::ID));
+        assert_eq!(game.deposits, [100, 0]);
+        assert_eq!(
+            GlobalStats::load(&accounts[7].data).unwrap().games_created,
+            1
+        );
+
+        for i in [2, 4] {
+            let mut accounts = create_accounts(&program_id, player, mint);
+            accounts[i].owner = Pubkey::new_unique();
+            assert_eq!(
+                process(&program_id, &mut accounts, create_game()),
+                Err(ProgramError::IllegalOwner)
+            );
+        }
+    }
+
+    #[test]
+    fn closing_needs_player_one_to_sign() {
+        install_stubs();
+        let program_id = Pubkey::new_unique();
+        let mut game = setup();
+        play_all(&mut game, &[(0, 0), (1, 0), (0, 1), (1, 1), (0, 2)]);
+        let (stats, _) = Pubkey::find_program_address(&["stats".as_bytes()], &program_id);
+        let mut accounts = [
+            TestAccount::new(game.players[0], false),
+            TestAccount::game(&program_id, &game),
+            TestAccount::new(Pubkey::new_unique(), false),
+            TestAccount::new(Pubkey::new_unique(), false),
+            TestAccount::new(spl_token::ID, false),
+            TestAccount::new(SYSTEM_PROGRAM_ID, false),
+            TestAccount::new(stats, false).owned_by(program_id),
+        ];
+        let close = Instruction::CloseGame {
+            keep_trophy: false,
+            create_ata_if_missing: false,
+            rent_recipient: None,
+        };
+        assert_eq!(
+            process(&program_id, &mut accounts, close),
+            Err(ProgramError::MissingRequiredSignature)
+        );
+    }
+
+    #[test]
+    fn an_open_game_with_a_secret_needs_the_secret() {
+        install_stubs();
+        let program_id = Pubkey::new_unique();
+        let secret = [7; 32];
+        let mut game = unaccepted(Pubkey::default());
+        game.join_secret_hash = hash(&secret).to_bytes();
+        for join_secret in [None, Some([8; 32])] {
+            let mut accounts = accept_accounts(&program_id, &game, Pubkey::new_unique());
+            assert_eq!(
+                process(&program_id, &mut accounts, accept(0, join_secret)),
+                Err(Error::InvalidJoinSecret.into())
+            );
+        }
+        let player_two = Pubkey::new_unique();
+        let mut accounts = accept_accounts(&program_id, &game, player_two);
+        process(&program_id, &mut accounts, accept(0, Some(secret))).unwrap();
+        assert_eq!(
+            Game::load(&accounts[1].data).unwrap().players[1],
+            player_two
+        );
+    }
+
+    #[test]
+    fn a_game_against_yourself_is_invalid_data() {
+        install_stubs();
+        let program_id = Pubkey::new_unique();
+        let mut game = setup();
+        game.players[1] = game.players[0];
+        let mut accounts = [
+            TestAccount::new(game.players[0], true),
+            TestAccount::game(&program_id, &game),
+        ];
+        assert_eq!(
+            process(
+                &program_id,
+                &mut accounts,
+                Instruction::PlayGame(Move { row: 0, col: 0 })
+            ),
+            Err(ProgramError::InvalidAccountData)
+        );
+        assert_eq!(Game::load(&accounts[1].data).unwrap().turns, 0);
+    }
+
+    // the fixed accounts of a CloseGame of a token game, its escrow holding both stakes
+    fn close_accounts(program_id: &Pubkey, game: &Game) -> Vec<TestAccount> {
+        let (escrow, _) = find_escrow_address(program_id, &game.stake_mint, None);
+        let (authority, _) = Pubkey::find_program_address(&["authority".as_bytes()], program_id);
+        let (stats, _) = Pubkey::find_program_address(&["stats".as_bytes()], program_id);
+        let mut stats = TestAccount::new(stats, false).owned_by(*program_id);
+        stats.data = GlobalStats {
+            games_created: 1,
+            games_settled: 0,
+            total_staked: 200,
+            is_initialized: true,
+        }
+        .try_to_vec()
+        .unwrap();
+        vec![
+            TestAccount::new(game.players[0], true),
+            TestAccount::game(program_id, game),
+            TestAccount::token(escrow, game.stake_mint, authority, 200),
+            TestAccount::new(authority, false),
+            TestAccount::new(spl_token::ID, false),
+            TestAccount::new(SYSTEM_PROGRAM_ID, false),
+            stats,
+        ]
+    }
+
+    fn close(keep_trophy: bool, rent_recipient: Option<Pubkey>) -> Instruction {
+        Instruction::CloseGame {
+            keep_trophy,
+            create_ata_if_missing: false,
+            rent_recipient,
+        }
+    }
+
+    #[test]
+    fn paying_someone_else_needs_the_winner_to_sign() {
+        install_stubs();
+        let program_id = Pubkey::new_unique();
+        let mut game = setup();
+        game.token_program = spl_token::ID;
+        play_all(&mut game, &[(0, 0), (1, 0), (0, 1), (1, 1), (0, 2)]);
+        let winner = game.players[0];
+        // a token account of someone who isn't playing
+        let payee = Pubkey::new_unique();
+        for signer in [false, true] {
+            let mut accounts = close_accounts(&program_id, &game);
+            accounts.push(TestAccount::token(
+                Pubkey::new_unique(),
+                game.stake_mint,
+                payee,
+                0,
+            ));
+            accounts.push(TestAccount::new(winner, signer));
+            let result = process(&program_id, &mut accounts, close(false, None));
+            if signer {
+                result.unwrap();
+            } else {
+                assert_eq!(result, Err(ProgramError::MissingRequiredSignature));
+            }
+        }
+    }
+
+    #[test]
+    fn create_game_rejects_a_game_account_of_the_wrong_size() {
+        install_stubs();
+        let program_id = Pubkey::new_unique();
+        for len in [Game::LEN - 1, Game::LEN + 1] {
+            let mut accounts =
+                create_accounts(&program_id, Pubkey::new_unique(), Pubkey::new_unique());
+            accounts[1].data = vec![0; len];
+            assert_eq!(
+                process(&program_id, &mut accounts, create_game()),
+                Err(ProgramError::InvalidAccountData)
+            );
+        }
+    }
+
+    // the accounts of a CancelGame of a token game, player one's refund account included
+    fn cancel_accounts(program_id: &Pubkey, game: &Game, signer: bool) -> Vec<TestAccount> {
+        let (escrow, _) = find_escrow_address(program_id, &game.stake_mint, None);
+        let (authority, _) = Pubkey::find_program_address(&["authority".as_bytes()], program_id);
+        vec![
+            TestAccount::new(game.players[0], signer),
+            TestAccount::game(program_id, game),
+            TestAccount::token(escrow, game.stake_mint, authority, 100),
+            TestAccount::token(Pubkey::new_unique(), game.stake_mint, game.players[0], 0),
+            TestAccount::new(authority, false),
+            TestAccount::new(spl_token::ID, false),
+        ]
+    }
+
+    #[test]
+    fn the_opponent_can_cancel_an_expired_challenge() {
+        install_stubs();
+        let program_id = Pubkey::new_unique();
+        let mut game = unaccepted(Pubkey::new_unique());
+        for (deadline, expired) in [(0, false), (NOW_SLOT, false), (NOW_SLOT - 1, true)] {
+            game.accept_deadline = deadline;
+            let mut accounts = cancel_accounts(&program_id, &game, false);
+            accounts.push(TestAccount::new(game.players[1], true));
+            let result = process(&program_id, &mut accounts, Instruction::CancelGame);
+            if expired {
+                result.unwrap();
+                let cancelled = Game::load(&accounts[1].data).unwrap();
+                assert_eq!(cancelled.state, GameState::Cancelled);
+            } else {
+                assert_eq!(result, Err(Error::ChallengeNotExpired.into()));
+            }
+        }
+    }
+
+    #[test]
+    fn a_kept_trophy_is_burned_for_its_rent() {
+        install_stubs();
+        let program_id = Pubkey::new_unique();
+        let mut game = setup();
+        game.token_program = spl_token::ID;
+        play_all(&mut game, &[(0, 0), (1, 0), (0, 1), (1, 1), (0, 2)]);
+        let winner = game.players[0];
+        let mut accounts = close_accounts(&program_id, &game);
+        accounts.push(TestAccount::token(
+            Pubkey::new_unique(),
+            game.stake_mint,
+            winner,
+            0,
+        ));
+        accounts[1].lamports = 1_000;
+        process(&program_id, &mut accounts, close(true, None)).unwrap();
+        let trophy = Game::load(&accounts[1].data).unwrap();
+        assert_eq!(
+            trophy.state,
+            GameState::Settled {
+                winner: Some(winner)
+            }
+        );
+        assert_eq!(accounts[1].lamports, 1_000);
+
+        let mut burn = [
+            TestAccount::new(winner, true),
+            TestAccount::game(&program_id, &game),
+        ];
+        assert_eq!(
+            process(&program_id, &mut burn, Instruction::BurnTrophy),
+            Err(Error::NotATrophy.into())
+        );
+        let mut burn = [
+            TestAccount::new(winner, true),
+            TestAccount::game(&program_id, &trophy),
+        ];
+        burn[1].lamports = 1_000;
+        process(&program_id, &mut burn, Instruction::BurnTrophy).unwrap();
+        assert_eq!((burn[0].lamports, burn[1].lamports), (1_000, 0));
+    }
+
+    #[test]
+    fn swapped_program_accounts_are_caught_by_name() {
+        install_stubs();
+        let program_id = Pubkey::new_unique();
+        let mut accounts = create_accounts(&program_id, Pubkey::new_unique(), Pubkey::new_unique());
+        accounts.swap(5, 6);
+        assert_eq!(
+            process(&program_id, &mut accounts, create_game()),
+            Err(ProgramError::IncorrectProgramId)
+        );
+        // a missing trailing account fails the parse instead of shifting the others
+        let mut accounts = create_accounts(&program_id, Pubkey::new_unique(), Pubkey::new_unique());
+        assert_eq!(
+            process(&program_id, &mut accounts[..7], create_game()),
+            Err(ProgramError::NotEnoughAccountKeys)
+        );
+    }
+
+    #[test]
+    fn a_game_past_its_duration_can_be_forced_into_a_draw() {
+        install_stubs();
+        let program_id = Pubkey::new_unique();
+        let mut game = setup();
+        game.max_duration_slots = 100;
+        play_all(&mut game, &[(0, 0)]);
+        for (started_slot, exceeded) in [(NOW_SLOT - 100, false), (NOW_SLOT - 101, true)] {
+            game.started_slot = started_slot;
+            let mut accounts = [
+                TestAccount::new(game.players[1], true),
+                TestAccount::game(&program_id, &game),
+            ];
+            let result = process(&program_id, &mut accounts, Instruction::ForceDraw);
+            if exceeded {
+                result.unwrap();
+                assert_eq!(
+                    Game::load(&accounts[1].data).unwrap().state,
+                    GameState::Draw
+                );
+            } else {
+                assert_eq!(result, Err(Error::DurationNotExceeded.into()));
+            }
+        }
+        // without a duration the game never runs out
+        game.max_duration_slots = 0;
+        let mut accounts = [
+            TestAccount::new(game.players[1], true),
+            TestAccount::game(&program_id, &game),
+        ];
+        assert_eq!(
+            process(&program_id, &mut accounts, Instruction::ForceDraw),
+            Err(Error::DurationNotExceeded.into())
+        );
+    }
+
+    #[test]
+    fn a_basket_needs_distinct_mints_and_non_zero_amounts() {
+        install_stubs();
+        let program_id = Pubkey::new_unique();
+        let mint = Pubkey::new_unique();
+        let other = Pubkey::new_unique();
+        let stake = |mint, amount| Stake { mint, amount };
+        for extra_stakes in [
+            vec![stake(mint, 10)],
+            vec![stake(other, 0)],
+            vec![stake(other, 10), stake(other, 20)],
+        ] {
+            let mut instruction = create_game();
+            if let Instruction::CreateGame {
+                extra_stakes: basket,
+                ..
+            } = &mut instruction
+            {
+                *basket = extra_stakes;
+            }
+            let mut accounts = create_accounts(&program_id, Pubkey::new_unique(), mint);
+            assert_eq!(
+                process(&program_id, &mut accounts, instruction),
+                Err(ProgramError::InvalidArgument)
+            );
+        }
+    }
+
+    #[test]
+    fn a_game_of_an_unknown_version_can_not_be_played() {
+        install_stubs();
+        let program_id = Pubkey::new_unique();
+        let game = setup();
+        let mut accounts = [
+            TestAccount::new(game.players[0], true),
+            TestAccount::game(&program_id, &game),
+        ];
+        accounts[1].data[Game::OFFSET_VERSION] = Game::VERSION + 1;
+        assert_eq!(
+            process(
+                &program_id,
+                &mut accounts,
+                Instruction::PlayGame(Move { row: 0, col: 0 })
+            ),
+            Err(Error::UnsupportedGameVersion.into())
+        );
+    }
+
+    #[test]
+    fn accepting_always_escrows_player_two_stake() {
+        install_stubs();
+        let program_id = Pubkey::new_unique();
+        let player_two = Pubkey::new_unique();
+        for (stake_amount_two, bond_amount) in [(100, 0), (60, 0), (100, 40)] {
+            let mut game = unaccepted(player_two);
+            game.stake_amount_two = stake_amount_two;
+            game.bond_amount = bond_amount;
+            let mut accounts = accept_accounts(&program_id, &game, player_two);
+            process(&program_id, &mut accounts, accept(0, None)).unwrap();
+            let accepted = Game::load(&accounts[1].data).unwrap();
+            let escrowed = if bond_amount != 0 {
+                bond_amount
+            } else {
+                stake_amount_two
+            };
+            assert_eq!(accepted.deposits[1], escrowed);
+        }
+        // player two has to be able to cover it
+        let mut game = unaccepted(player_two);
+        game.stake_amount_two = 101;
+        let mut accounts = accept_accounts(&program_id, &game, player_two);
+        assert_eq!(
+            process(&program_id, &mut accounts, accept(0, None)),
+            Err(ProgramError::InsufficientFunds)
+        );
+    }
+
+    #[test]
+    fn inspecting_an_escrow_only_reads_the_mint_escrow() {
+        install_stubs();
+        let program_id = Pubkey::new_unique();
+        let mint = Pubkey::new_unique();
+        let (escrow, _) = find_escrow_address(&program_id, &mint, None);
+        let (authority, _) = Pubkey::find_program_address(&["authority".as_bytes()], &program_id);
+        let inspect = || Instruction::InspectEscrow { mint };
+        let mut accounts = [TestAccount::token(escrow, mint, authority, 300)];
+        take_logs();
+        process(&program_id, &mut accounts, inspect()).unwrap();
+        assert_eq!(
+            take_logs(),
+            [
+                format!("escrow: {escrow}"),
+                format!("mint: {mint} (expected {mint}, matches: true)"),
+                format!("owner: {authority} (expected authority {authority}, matches: true)"),
+                "balance: 300".to_string(),
+            ]
+        );
+        // a mismatched owner is logged, not an error
+        let owner = Pubkey::new_unique();
+        let mut accounts = [TestAccount::token(escrow, mint, owner, 0)];
+        process(&program_id, &mut accounts, inspect()).unwrap();
+        let logs = take_logs();
+        assert_eq!(
+            logs[2],
+            format!("owner: {owner} (expected authority {authority}, matches: false)")
+        );
+        assert_eq!(logs[3], "balance: 0");
+        let mut accounts = [TestAccount::token(
+            Pubkey::new_unique(),
+            mint,
+            authority,
+            300,
+        )];
+        assert_eq!(
+            process(&program_id, &mut accounts, inspect()),
+            Err(ProgramError::InvalidArgument)
+        );
+    }
+
+    #[test]
+    fn an_early_draw_needs_the_toggle_and_a_dead_board() {
+        install_stubs();
+        let program_id = Pubkey::new_unique();
+        // every line holds both symbols, only (1, 2) is left
+        let dead = [
+            (0, 0),
+            (0, 1),
+            (0, 2),
+            (1, 0),
+            (1, 1),
+            (2, 0),
+            (2, 1),
+            (2, 2),
+        ];
+        let claim = |game: &Game| {
+            let mut accounts = [
+                TestAccount::new(game.players[0], true),
+                TestAccount::game(&program_id, game),
+            ];
+            process(&program_id, &mut accounts, Instruction::ClaimDraw)
+                .map(|_| Game::load(&accounts[1].data).unwrap().state)
+        };
+        let mut game = setup();
+        play_all(&mut game, &dead);
+        assert_eq!(claim(&game), Err(Error::EarlyDrawNotAllowed.into()));
+        game.allow_early_draw = true;
+        assert_eq!(claim(&game), Ok(GameState::Draw));
+
+        let mut game = setup();
+        game.allow_early_draw = true;
+        play_all(&mut game, &dead[..2]);
+        assert_eq!(claim(&game), Err(Error::NotDrawn.into()));
+    }
+
+    #[test]
+    fn programs_are_not_taken_as_token_accounts() {
+        install_stubs();
+        let program_id = Pubkey::new_unique();
+        let player_two = Pubkey::new_unique();
+        let game = unaccepted(player_two);
+        for program in [SYSTEM_PROGRAM_ID, spl_token::ID, spl_token_2022::ID] {
+            let mut accounts = accept_accounts(&program_id, &game, player_two);
+            accounts[3].key = program;
+            assert_eq!(
+                process(&program_id, &mut accounts, accept(0, None)),
+                Err(Error::ProgramAsTokenAccount.into())
+            );
+            let mut accounts = create_accounts(&program_id, Pubkey::new_unique(), game.stake_mint);
+            accounts[4].key = program;
+            assert_eq!(
+                process(&program_id, &mut accounts, create_game()),
+                Err(Error::ProgramAsTokenAccount.into())
+            );
+        }
+    }
+
+    #[test]
+    fn verify_pdas_checks_both_addresses_and_the_escrow_data() {
+        let program_id = Pubkey::new_unique();
+        let mint = Pubkey::new_unique();
+        let game = Pubkey::new_unique();
+        let (authority_key, _) =
+            Pubkey::find_program_address(&["authority".as_bytes()], &program_id);
+        let mut authority = TestAccount::new(authority_key, false);
+        for dedicated in [None, Some(&game)] {
+            let (escrow_key, _) = find_escrow_address(&program_id, &mint, dedicated);
+            let mut escrow = TestAccount::token(escrow_key, mint, authority_key, 0);
+            assert!(verify_pdas(
+                &program_id,
+                &mint,
+                dedicated,
+                &escrow.info(),
+                &authority.info()
+            )
+            .is_ok());
+
+            let mut other = TestAccount::new(Pubkey::new_unique(), false);
+            assert_eq!(
+                verify_pdas(
+                    &program_id,
+                    &mint,
+                    dedicated,
+                    &other.info(),
+                    &authority.info()
+                ),
+                Err(ProgramError::InvalidArgument)
+            );
+            assert_eq!(
+                verify_pdas(&program_id, &mint, dedicated, &escrow.info(), &other.info()),
+                Err(ProgramError::InvalidArgument)
+            );
+            for mut tampered in [
+                TestAccount::token(escrow_key, Pubkey::new_unique(), authority_key, 0),
+                TestAccount::token(escrow_key, mint, Pubkey::new_unique(), 0),
+            ] {
+                assert_eq!(
+                    verify_pdas(
+                        &program_id,
+                        &mint,
+                        dedicated,
+                        &tampered.info(),
+                        &authority.info()
+                    ),
+                    Err(ProgramError::InvalidAccountData)
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn accepting_sticks_to_the_game_token_program() {
+        install_stubs();
+        let program_id = Pubkey::new_unique();
+        let player_two = Pubkey::new_unique();
+        let game = unaccepted(player_two);
+        // an escrow of the other token program
+        let mut accounts = accept_accounts(&program_id, &game, player_two);
+        accounts[2].owner = spl_token_2022::ID;
+        assert_eq!(
+            process(&program_id, &mut accounts, accept(0, None)),
+            Err(ProgramError::IncorrectProgramId)
+        );
+        // the other token program itself
+        let mut accounts = accept_accounts(&program_id, &game, player_two);
+        accounts[4].key = spl_token_2022::ID;
+        assert_eq!(
+            process(&program_id, &mut accounts, accept(0, None)),
+            Err(ProgramError::IncorrectProgramId)
+        );
+    }
+
+    #[test]
+    fn a_missing_winner_account_is_only_created_at_its_ata_address() {
+        install_stubs();
+        let program_id = Pubkey::new_unique();
+        let mut game = setup();
+        game.token_program = spl_token::ID;
+        play_all(&mut game, &[(0, 0), (1, 0), (0, 1), (1, 1), (0, 2)]);
+        let winner = game.players[0];
+        let ata =
+            get_associated_token_address_with_program_id(&winner, &game.stake_mint, &spl_token::ID);
+        let close_creating = || Instruction::CloseGame {
+            keep_trophy: false,
+            create_ata_if_missing: true,
+            rent_recipient: None,
+        };
+        for (token_account, associated_program, error) in [
+            (
+                Pubkey::new_unique(),
+                spl_associated_token_account::ID,
+                ProgramError::InvalidArgument,
+            ),
+            (ata, Pubkey::new_unique(), ProgramError::IncorrectProgramId),
+        ] {
+            let mut accounts = close_accounts(&program_id, &game);
+            accounts.extend([
+                TestAccount::new(token_account, false),
+                TestAccount::new(game.stake_mint, false).owned_by(spl_token::ID),
+                TestAccount::new(winner, false),
+                TestAccount::new(associated_program, false),
+            ]);
+            assert_eq!(
+                process(&program_id, &mut accounts, close_creating()),
+                Err(error)
+            );
+        }
+    }
+
+    #[test]
+    fn legal_moves_are_returned_borsh_encoded() {
+        install_stubs();
+        let program_id = Pubkey::new_unique();
+        let mut game = setup();
+        play_all(&mut game, &[(0, 0), (1, 1), (2, 2)]);
+        let mut accounts = [TestAccount::game(&program_id, &game)];
+        process(&program_id, &mut accounts, Instruction::GetLegalMoves).unwrap();
+        let moves = Vec::<Move>::try_from_slice(&return_data()).unwrap();
+        assert_eq!(moves, game.legal_moves());
+        assert_eq!(moves.len(), 6);
+        assert!(!moves.contains(&Move { row: 1, col: 1 }));
+    }
+
+    #[test]
+    fn create_game_records_the_creation_slot() {
+        install_stubs();
+        let program_id = Pubkey::new_unique();
+        let mut accounts = create_accounts(&program_id, Pubkey::new_unique(), Pubkey::new_unique());
+        process(&program_id, &mut accounts, create_game()).unwrap();
+        let game = Game::load(&accounts[1].data).unwrap();
+        assert_eq!(game.created_slot, NOW_SLOT);
+        assert_eq!(game.last_move_slot, NOW_SLOT);
+        assert_eq!(game.game_id, Game::derive_id(&game.players, NOW_SLOT));
+    }
+
+    #[test]
+    fn only_a_casual_game_can_be_created_without_a_stake() {
+        install_stubs();
+        let program_id = Pubkey::new_unique();
+        // a casual game stakes nothing on both sides, in lamports and without a fee
+        for (stake_amount, stake_amount_two, mint, fee_bps) in [
+            (0, 100, SYSTEM_PROGRAM_ID, 0),
+            (100, 0, Pubkey::new_unique(), 0),
+            (0, 0, Pubkey::new_unique(), 0),
+            (0, 0, SYSTEM_PROGRAM_ID, 100),
+        ] {
+            let mut instruction = create_game();
+            if let Instruction::CreateGame {
+                stake_amount: one,
+                stake_amount_two: two,
+                fee_bps: fee,
+                fee_collector,
+                ..
+            } = &mut instruction
+            {
+                (*one, *two, *fee) = (stake_amount, stake_amount_two, fee_bps);
+                *fee_collector = Pubkey::new_unique();
+            }
+            let mut accounts = create_accounts(&program_id, Pubkey::new_unique(), mint);
+            assert_eq!(
+                process(&program_id, &mut accounts, instruction),
+                Err(ProgramError::InvalidArgument)
+            );
+        }
+    }
+
+    #[test]
+    fn a_game_with_symbols_on_the_board_can_not_be_accepted() {
+        install_stubs();
+        let program_id = Pubkey::new_unique();
+        let player_two = Pubkey::new_unique();
+        let mut game = unaccepted(player_two);
+        game.board[1][1] = Some(Symbol::O);
+        let mut accounts = accept_accounts(&program_id, &game, player_two);
+        assert_eq!(
+            process(&program_id, &mut accounts, accept(0, None)),
+            Err(ProgramError::InvalidAccountData)
+        );
+        assert_eq!(
+            Game::load(&accounts[1].data).unwrap().state,
+            GameState::Unaccepted
+        );
+    }
+
+    #[test]
+    fn accept_game_writes_the_game_before_moving_the_stake() {
+        install_stubs();
+        let program_id = Pubkey::new_unique();
+        let player_two = Pubkey::new_unique();
+        let game = unaccepted(player_two);
+        let mut accounts = accept_accounts(&program_id, &game, player_two);
+        // the failed transfer would revert the write on chain, here it shows it came first
+        fail_cpis(true);
+        let result = process(&program_id, &mut accounts, accept(0, None));
+        fail_cpis(false);
+        assert_eq!(result, Err(CPI_ERROR));
+        let accepted = Game::load(&accounts[1].data).unwrap();
+        assert_eq!(accepted.state, GameState::Ongoing);
+        assert_eq!(accepted.deposits, [100, 100]);
+    }
+
+    #[test]
+    fn the_rent_refund_goes_to_a_signing_participant_only() {
+        install_stubs();
+        let program_id = Pubkey::new_unique();
+        let mut game = setup();
+        game.token_program = spl_token::ID;
+        play_all(&mut game, &[(0, 0), (1, 0), (0, 1), (1, 1), (0, 2)]);
+        let [winner, loser] = game.players;
+        let stranger = Pubkey::new_unique();
+        for (recipient, signer, expected) in [
+            (stranger, true, Err(ProgramError::InvalidArgument)),
+            (loser, false, Err(ProgramError::MissingRequiredSignature)),
+            (loser, true, Ok(())),
+        ] {
+            let mut accounts = close_accounts(&program_id, &game);
+            accounts[1].lamports = 1_000;
+            accounts.push(TestAccount::token(
+                Pubkey::new_unique(),
+                game.stake_mint,
+                winner,
+                0,
+            ));
+            accounts.push(TestAccount::new(recipient, signer));
+            assert_eq!(
+                process(&program_id, &mut accounts, close(false, Some(recipient))),
+                expected
+            );
+            if expected.is_ok() {
+                assert_eq!((accounts[1].lamports, accounts[8].lamports), (0, 1_000));
+                assert_eq!(accounts[0].lamports, 0);
+            }
+        }
+    }
+
+    #[test]
+    fn cancel_game_checks_every_account_before_writing() {
+        install_stubs();
+        let program_id = Pubkey::new_unique();
+        let game = unaccepted(Pubkey::new_unique());
+        let mut short_escrow = cancel_accounts(&program_id, &game, true);
+        short_escrow[2] = TestAccount::token(
+            short_escrow[2].key,
+            game.stake_mint,
+            short_escrow[4].key,
+            99,
+        );
+        let mut foreign_refund = cancel_accounts(&program_id, &game, true);
+        foreign_refund[3] = TestAccount::token(
+            Pubkey::new_unique(),
+            game.stake_mint,
+            Pubkey::new_unique(),
+            0,
+        );
+        for (mut accounts, error) in [
+            (short_escrow, ProgramError::InsufficientFunds),
+            (foreign_refund, ProgramError::InvalidArgument),
+        ] {
+            assert_eq!(
+                process(&program_id, &mut accounts, Instruction::CancelGame),
+                Err(error)
+            );
+            assert_eq!(
+                Game::load(&accounts[1].data).unwrap().state,
+                GameState::Unaccepted
+            );
+        }
+        let mut accounts = cancel_accounts(&program_id, &game, true);
+        process(&program_id, &mut accounts, Instruction::CancelGame).unwrap();
+        assert_eq!(
+            Game::load(&accounts[1].data).unwrap().state,
+            GameState::Cancelled
+        );
+    }
+
+    #[test]
+    fn close_and_cancel_only_drain_the_derived_escrow() {
+        install_stubs();
+        let program_id = Pubkey::new_unique();
+        let (authority, _) = Pubkey::find_program_address(&["authority".as_bytes()], &program_id);
+        let game = unaccepted(Pubkey::new_unique());
+        let mut accounts = cancel_accounts(&program_id, &game, true);
+        accounts[2] = TestAccount::token(Pubkey::new_unique(), game.stake_mint, authority, 100);
+        assert_eq!(
+            process(&program_id, &mut accounts, Instruction::CancelGame),
+            Err(ProgramError::InvalidArgument)
+        );
+
+        // the shared escrow is not the one of a game with a dedicated escrow
+        let mut game = setup();
+        game.token_program = spl_token::ID;
+        game.dedicated_escrow = true;
+        play_all(&mut game, &[(0, 0), (1, 0), (0, 1), (1, 1), (0, 2)]);
+        let mut accounts = close_accounts(&program_id, &game);
+        assert_eq!(
+            process(&program_id, &mut accounts, close(false, None)),
+            Err(ProgramError::InvalidArgument)
+        );
+    }
+
+    #[test]
+    fn accepting_returns_the_starting_snapshot() {
+        install_stubs();
+        let program_id = Pubkey::new_unique();
+        let player_two = Pubkey::new_unique();
+        let mut game = unaccepted(player_two);
+        game.higher_staker_moves_second = true;
+        game.stake_amount_two = 50;
+        let mut accounts = accept_accounts(&program_id, &game, player_two);
+        process(&program_id, &mut accounts, accept(0, None)).unwrap();
+        let snapshot = match GameEvent::try_from_slice(&return_data()).unwrap() {
+            GameEvent::Accepted(snapshot) => snapshot,
+            event => panic!("unexpected event {:?}", event),
+        };
+        assert_eq!(snapshot, Game::load(&accounts[1].data).unwrap().snapshot());
+        assert_eq!(snapshot.state, GameState::Ongoing);
+        // player one staked more, so player two opens
+        assert_eq!(snapshot.next_player, player_two);
+    }
+
+    #[test]
+    fn a_game_can_not_be_created_against_yourself() {
+        install_stubs();
+        let program_id = Pubkey::new_unique();
+        let player = Pubkey::new_unique();
+        let mut instruction = create_game();
+        if let Instruction::CreateGame { player_two, .. } = &mut instruction {
+            *player_two = player;
+        }
+        let mut accounts = create_accounts(&program_id, player, Pubkey::new_unique());
+        assert_eq!(
+            process(&program_id, &mut accounts, instruction),
+            Err(ProgramError::InvalidArgument)
+        );
+    }
+
+    #[test]
+    fn accepting_needs_a_live_escrow_of_the_stake_mint() {
+        install_stubs();
+        let program_id = Pubkey::new_unique();
+        let player_two = Pubkey::new_unique();
+        let game = unaccepted(player_two);
+        let (authority, _) = Pubkey::find_program_address(&["authority".as_bytes()], &program_id);
+        let escrow = find_escrow_address(&program_id, &game.stake_mint, None).0;
+        for (tampered, error) in [
+            (
+                TestAccount::token(escrow, Pubkey::new_unique(), authority, 100),
+                ProgramError::InvalidAccountData,
+            ),
+            (
+                TestAccount::token(escrow, game.stake_mint, player_two, 100),
+                ProgramError::InvalidAccountData,
+            ),
+            (
+                TestAccount {
+                    data: vec![0; Account::LEN],
+                    ..TestAccount::new(escrow, false).owned_by(spl_token::ID)
+                },
+                ProgramError::UninitializedAccount,
+            ),
+        ] {
+            let mut accounts = accept_accounts(&program_id, &game, player_two);
+            accounts[2] = tampered;
+            assert_eq!(
+                process(&program_id, &mut accounts, accept(0, None)),
+                Err(error)
+            );
+        }
+    }
+
+    #[test]
+    fn a_rematch_restarts_a_finished_game_with_the_other_opener() {
+        install_stubs();
+        let program_id = Pubkey::new_unique();
+        let (authority, _) = Pubkey::find_program_address(&["authority".as_bytes()], &program_id);
+        let rematch_accounts = |game: &Game| {
+            let escrow = find_escrow_address(&program_id, &game.stake_mint, None).0;
+            [
+                TestAccount::new(game.players[0], true),
+                TestAccount::new(game.players[1], true),
+                TestAccount::game(&program_id, game),
+                TestAccount::token(escrow, game.stake_mint, authority, 200),
+                TestAccount::new(authority, false),
+                TestAccount::token(Pubkey::new_unique(), game.stake_mint, game.players[0], 0),
+                TestAccount::token(Pubkey::new_unique(), game.stake_mint, game.players[1], 100),
+                TestAccount::new(spl_token::ID, false),
+            ]
+        };
+        let mut game = setup();
+        game.token_program = spl_token::ID;
+        assert_eq!(
+            process(
+                &program_id,
+                &mut rematch_accounts(&game),
+                Instruction::Rematch
+            ),
+            Err(Error::OngoingGame.into())
+        );
+        play_all(&mut game, &[(0, 0), (1, 0), (0, 1), (1, 1), (0, 2)]);
+        // the stakes are moved by the game's token program only
+        let mut accounts = rematch_accounts(&game);
+        accounts[7] = TestAccount::new(spl_token_2022::ID, false);
+        assert_eq!(
+            process(&program_id, &mut accounts, Instruction::Rematch),
+            Err(ProgramError::IncorrectProgramId)
+        );
+        let mut accounts = rematch_accounts(&game);
+        process(&program_id, &mut accounts, Instruction::Rematch).unwrap();
+        let rematch = Game::load(&accounts[2].data).unwrap();
+        assert_eq!(rematch.state, GameState::Ongoing);
+        assert_eq!(rematch.turns, 0);
+        assert_eq!(rematch.deposits, [100, 100]);
+        assert_eq!(rematch.current_player(), game.players[1]);
+        assert_eq!(rematch.started_slot, NOW_SLOT);
+    }
+
+    #[test]
+    fn create_game_takes_a_board_size_and_win_length_in_range() {
+        install_stubs();
+        let program_id = Pubkey::new_unique();
+        let sized = |size, len| {
+            let mut instruction = create_game();
+            if let Instruction::CreateGame {
+                board_size,
+                win_len,
+                ..
+            } = &mut instruction
+            {
+                (*board_size, *win_len) = (size, len);
+            }
+            instruction
+        };
+        for (size, len) in [(2, 2), (6, 3), (3, 4), (4, 2)] {
+            let mut accounts =
+                create_accounts(&program_id, Pubkey::new_unique(), Pubkey::new_unique());
+            assert_eq!(
+                process(&program_id, &mut accounts, sized(size, len)),
+                Err(Error::InvalidBoardSize.into())
+            );
+        }
+        let mut accounts = create_accounts(&program_id, Pubkey::new_unique(), Pubkey::new_unique());
+        process(&program_id, &mut accounts, sized(5, 4)).unwrap();
+        let game = Game::load(&accounts[1].data).unwrap();
+        assert_eq!((game.size, game.win_len, game.tiles()), (5, 4, 25));
+    }
+
+    #[test]
+    fn only_an_empty_escrow_can_be_closed() {
+        install_stubs();
+        let program_id = Pubkey::new_unique();
+        let mint = Pubkey::new_unique();
+        let (escrow, _) = find_escrow_address(&program_id, &mint, None);
+        let (authority, _) = Pubkey::find_program_address(&["authority".as_bytes()], &program_id);
+        let close_escrow = |amount, signer| {
+            let mut accounts = [
+                TestAccount::new(Pubkey::new_unique(), signer),
+                TestAccount::token(escrow, mint, authority, amount),
+                TestAccount::new(authority, false),
+                TestAccount::new(spl_token::ID, false),
+            ];
+            process(
+                &program_id,
+                &mut accounts,
+                Instruction::CloseEscrow { mint, game: None },
+            )
+        };
+        assert_eq!(
+            close_escrow(0, false),
+            Err(ProgramError::MissingRequiredSignature)
+        );
+        assert_eq!(close_escrow(1, true), Err(Error::EscrowNotEmpty.into()));
+        assert_eq!(close_escrow(0, true), Ok(()));
+    }
+
+    #[test]
+    fn both_players_signing_agree_to_a_draw() {
+        install_stubs();
+        let program_id = Pubkey::new_unique();
+        let mut game = setup();
+        play_all(&mut game, &[(0, 0), (1, 1)]);
+        let agree = |game: &Game, two: Pubkey, signer: bool| {
+            let mut accounts = [
+                TestAccount::new(game.players[0], true),
+                TestAccount::new(two, signer),
+                TestAccount::game(&program_id, game),
+            ];
+            process(&program_id, &mut accounts, Instruction::AgreeDraw)
+                .map(|_| Game::load(&accounts[2].data).unwrap())
+        };
+        assert_eq!(
+            agree(&game, game.players[1], false).unwrap_err(),
+            ProgramError::MissingRequiredSignature
+        );
+        assert_eq!(
+            agree(&game, Pubkey::new_unique(), true).unwrap_err(),
+            Error::CanNotPlay.into()
+        );
+        let drawn = agree(&game, game.players[1], true).unwrap();
+        assert_eq!(drawn.state, GameState::Draw);
+        assert!(drawn.forfeited);
+        assert_eq!(
+            drawn.payouts(),
+            vec![(game.players[0], 100), (game.players[1], 100)]
+        );
+        assert_eq!(
+            agree(&drawn, game.players[1], true).unwrap_err(),
+            Error::GameAlreadyOver.into()
+        );
+    }
+
+    #[test]
+    fn a_cancelled_game_is_kept_out_of_play_and_settlement() {
+        install_stubs();
+        let program_id = Pubkey::new_unique();
+        let mut game = unaccepted(Pubkey::new_unique());
+        game.state = GameState::Cancelled;
+        let mut accounts = [
+            TestAccount::new(game.players[0], true),
+            TestAccount::game(&program_id, &game),
+        ];
+        assert_eq!(
+            process(
+                &program_id,
+                &mut accounts,
+                Instruction::PlayGame(Move { row: 0, col: 0 })
+            ),
+            Err(Error::GameCancelled.into())
+        );
+        let mut accounts = close_accounts(&program_id, &game);
+        assert_eq!(
+            process(&program_id, &mut accounts, close(false, None)),
+            Err(Error::UnclosableGame.into())
+        );
+        let mut accounts = cancel_accounts(&program_id, &game, true);
+        assert_eq!(
+            process(&program_id, &mut accounts, Instruction::CancelGame),
+            Err(Error::UnclosableGame.into())
+        );
+    }
+
+    #[test]
+    fn a_derived_game_account_needs_no_signature() {
+        install_stubs();
+        let program_id = Pubkey::new_unique();
+        let player = Pubkey::new_unique();
+        let player_two = Pubkey::new_unique();
+        let with_nonce = |nonce| {
+            let mut instruction = create_game();
+            if let Instruction::CreateGame {
+                player_two: two,
+                game_nonce,
+                ..
+            } = &mut instruction
+            {
+                (*two, *game_nonce) = (player_two, nonce);
+            }
+            instruction
+        };
+        let mut accounts = create_accounts(&program_id, player, Pubkey::new_unique());
+        accounts[1].signer = false;
+        assert_eq!(
+            process(&program_id, &mut accounts, with_nonce(None)),
+            Err(ProgramError::MissingRequiredSignature)
+        );
+        // the nonce has to derive the account that is passed
+        assert_eq!(
+            process(&program_id, &mut accounts, with_nonce(Some(7))),
+            Err(ProgramError::InvalidArgument)
+        );
+        accounts[1].key = find_game_address(&program_id, &player, &player_two, 7).0;
+        process(&program_id, &mut accounts, with_nonce(Some(7))).unwrap();
+        assert_eq!(
+            Game::load(&accounts[1].data).unwrap().players,
+            [player, player_two]
+        );
+    }
+
+    #[test]
+    fn a_token_2022_mint_is_staked_through_token_2022() {
+        install_stubs();
+        let program_id = Pubkey::new_unique();
+        let mint = Pubkey::new_unique();
+        let mut accounts = create_accounts(&program_id, Pubkey::new_unique(), mint);
+        for i in [2, 3, 4] {
+            accounts[i].owner = spl_token_2022::ID;
+        }
+        accounts[5].key = spl_token_2022::ID;
+        process(&program_id, &mut accounts, create_game()).unwrap();
+        let game = Game::load(&accounts[1].data).unwrap();
+        assert_eq!(game.token_program, spl_token_2022::ID);
+
+        // the mint decides, the legacy program can't move a token-2022 stake
+        let mut accounts = create_accounts(&program_id, Pubkey::new_unique(), mint);
+        accounts[2].owner = spl_token_2022::ID;
+        assert_eq!(
+            process(&program_id, &mut accounts, create_game()),
+            Err(ProgramError::IllegalOwner)
+        );
+    }
+
+    #[test]
+    fn cancel_game_only_drains_an_escrow_of_the_token_program() {
+        install_stubs();
+        let program_id = Pubkey::new_unique();
+        let game = unaccepted(Pubkey::new_unique());
+        for owner in [program_id, spl_token_2022::ID] {
+            let mut accounts = cancel_accounts(&program_id, &game, true);
+            accounts[2].owner = owner;
+            assert_eq!(
+                process(&program_id, &mut accounts, Instruction::CancelGame),
+                Err(ProgramError::InvalidArgument)
+            );
+        }
+    }
+
+    #[test]
+    fn log_board_logs_the_rows_turn_and_state_without_writing() {
+        install_stubs();
+        let program_id = Pubkey::new_unique();
+        let mut game = setup();
+        play_all(&mut game, &[(0, 0), (1, 1)]);
+        let mut accounts = [TestAccount::game(&program_id, &game)];
+        let before = accounts[0].data.clone();
+        take_logs();
+        process(&program_id, &mut accounts, Instruction::LogBoard).unwrap();
+        assert_eq!(
+            take_logs(),
+            vec![
+                "X--".to_string(),
+                "-O-".to_string(),
+                "---".to_string(),
+                format!("turn: {} (X)", game.players[0]),
+                "state: ongoing".to_string(),
+            ]
+        );
+        assert_eq!(accounts[0].data, before);
+    }
+
+    #[test]
+    fn a_result_that_no_longer_fits_the_account_fails_instead_of_panicking() {
+        install_stubs();
+        let program_id = Pubkey::new_unique();
+        let mut game = setup();
+        play_all(&mut game, &[(0, 0), (1, 0), (0, 1), (1, 1)]);
+        let mut accounts = [
+            TestAccount::new(game.players[0], true),
+            TestAccount::game(&program_id, &game),
+        ];
+        // sized for the ongoing game, the winner's key doesn't fit in it
+        accounts[1].data.truncate(game.try_to_vec().unwrap().len());
+        assert_eq!(
+            process(
+                &program_id,
+                &mut accounts,
+                Instruction::PlayGame(Move { row: 0, col: 2 })
+            ),
+            Err(ProgramError::AccountDataTooSmall)
+        );
+    }
+
+    #[test]
+    fn accepting_a_zero_stake_game_makes_no_transfer() {
+        install_stubs();
+        let program_id = Pubkey::new_unique();
+        let player_two = Pubkey::new_unique();
+        let mut game = unaccepted(player_two);
+        game.stake_amount = 0;
+        game.stake_amount_two = 0;
+        game.deposits = [0, 0];
+        let mut accounts = accept_accounts(&program_id, &game, player_two);
+        take_cpis();
+        process(&program_id, &mut accounts, accept(0, None)).unwrap();
+        assert!(take_cpis().is_empty());
+        let accepted = Game::load(&accounts[1].data).unwrap();
+        assert_eq!(accepted.state, GameState::Ongoing);
+        assert_eq!(accepted.deposits, [0, 0]);
+        // a staked game still makes its transfer
+        let mut accounts = accept_accounts(&program_id, &unaccepted(player_two), player_two);
+        process(&program_id, &mut accounts, accept(0, None)).unwrap();
+        assert_eq!(take_cpis().len(), 1);
+    }
+
+    #[test]
+    fn double_or_nothing_doubles_the_stake_out_of_the_losers_account() {
+        install_stubs();
+        let program_id = Pubkey::new_unique();
+        let mut game = setup();
+        game.token_program = spl_token::ID;
+        play_all(&mut game, &[(0, 0), (1, 0), (0, 1), (1, 1), (0, 2)]);
+        let loser = game.players[1];
+        let (escrow, _) = find_escrow_address(&program_id, &game.stake_mint, None);
+        let (authority, _) = Pubkey::find_program_address(&["authority".as_bytes()], &program_id);
+        let loser_account = Pubkey::new_unique();
+        let accounts = |token_program: Pubkey| {
+            [
+                TestAccount::new(game.players[0], true),
+                TestAccount::new(loser, true),
+                TestAccount::game(&program_id, &game),
+                TestAccount::token(escrow, game.stake_mint, authority, 200),
+                TestAccount::token(loser_account, game.stake_mint, loser, 200),
+                TestAccount::new(token_program, false),
+            ]
+        };
+        // the token program has to be the one the game was created with
+        assert_eq!(
+            process(
+                &program_id,
+                &mut accounts(spl_token_2022::ID),
+                Instruction::DoubleOrNothing
+            ),
+            Err(ProgramError::IncorrectProgramId)
+        );
+        let mut accounts = accounts(spl_token::ID);
+        take_cpis();
+        process(&program_id, &mut accounts, Instruction::DoubleOrNothing).unwrap();
+        // the winnings stay escrowed, the loser matches them
+        assert_eq!(
+            take_cpis(),
+            vec![
+                transfer_instruction(&spl_token::ID, &loser_account, &escrow, &loser, 200).unwrap()
+            ]
+        );
+        let doubled = Game::load(&accounts[2].data).unwrap();
+        assert_eq!((doubled.stake_amount, doubled.stake_amount_two), (200, 200));
+        assert_eq!(doubled.deposits, [200, 200]);
+        assert_eq!(doubled.state, GameState::Ongoing);
+        assert_eq!(doubled.turns, 0);
+    }
+
+    // a bonded game won by player one, each player escrowed a bond of 10
+    fn bonded_win() -> Game {
+        let mut game = setup();
+        game.bond_amount = 10;
+        game.deposits = [10, 10];
+        game.token_program = spl_token::ID;
+        play_all(&mut game, &[(0, 0), (1, 0), (0, 1), (1, 1), (0, 2)]);
+        game
+    }
+
+    #[test]
+    fn an_honest_loser_pays_the_stake_and_both_bonds_are_returned() {
+        install_stubs();
+        let program_id = Pubkey::new_unique();
+        let game = bonded_win();
+        let [winner, loser] = game.players;
+        let (escrow, _) = find_escrow_address(&program_id, &game.stake_mint, None);
+        let (authority, _) = Pubkey::find_program_address(&["authority".as_bytes()], &program_id);
+        let refund_accounts = [Pubkey::new_unique(), Pubkey::new_unique()];
+        let (loser_account, winner_account) = (Pubkey::new_unique(), Pubkey::new_unique());
+        let mut accounts = close_accounts(&program_id, &game);
+        for (refund_account, player) in refund_accounts.iter().zip(game.players) {
+            accounts.push(TestAccount::token(
+                *refund_account,
+                game.stake_mint,
+                player,
+                0,
+            ));
+        }
+        accounts.push(TestAccount::new(loser, true));
+        accounts.push(TestAccount::token(
+            loser_account,
+            game.stake_mint,
+            loser,
+            100,
+        ));
+        accounts.push(TestAccount::token(
+            winner_account,
+            game.stake_mint,
+            winner,
+            0,
+        ));
+        take_cpis();
+        process(&program_id, &mut accounts, close(false, None)).unwrap();
+        let transfer = |source, destination, authority, amount| {
+            transfer_instruction(&spl_token::ID, source, destination, authority, amount).unwrap()
+        };
+        assert_eq!(
+            take_cpis(),
+            vec![
+                transfer(&escrow, &refund_accounts[0], &authority, 10),
+                transfer(&escrow, &refund_accounts[1], &authority, 10),
+                transfer(&loser_account, &winner_account, &loser, 100),
+            ]
+        );
+    }
+
+    #[test]
+    fn a_loser_who_never_pays_has_the_bond_slashed_to_the_winner() {
+        install_stubs();
+        let program_id = Pubkey::new_unique();
+        let mut game = bonded_win();
+        game.last_move_slot = NOW_SLOT - SETTLEMENT_WINDOW_SLOTS - 1;
+        let winner = game.players[0];
+        let (escrow, _) = find_escrow_address(&program_id, &game.stake_mint, None);
+        let (authority, _) = Pubkey::find_program_address(&["authority".as_bytes()], &program_id);
+        let winner_account = Pubkey::new_unique();
+        // the close accounts without the system program, the winner's account before the
+        // token program
+        let mut accounts = close_accounts(&program_id, &game);
+        accounts.remove(5);
+        accounts.insert(
+            4,
+            TestAccount::token(winner_account, game.stake_mint, winner, 0),
+        );
+        take_cpis();
+        process(&program_id, &mut accounts, Instruction::SlashBond).unwrap();
+        assert_eq!(
+            take_cpis(),
+            vec![
+                transfer_instruction(&spl_token::ID, &escrow, &winner_account, &authority, 20)
+                    .unwrap()
+            ]
+        );
+        let slashed = Game::load(&accounts[1].data).unwrap();
+        assert_eq!(
+            slashed.state,
+            GameState::Settled {
+                winner: Some(winner)
+            }
+        );
+        assert_eq!(
+            GlobalStats::load(&accounts[6].data).unwrap().games_settled,
+            1
+        );
+    }
+
+    #[test]
+    fn resigning_hands_the_opponent_both_stakes() {
+        install_stubs();
+        let program_id = Pubkey::new_unique();
+        let mut game = setup();
+        game.token_program = spl_token::ID;
+        play_all(&mut game, &[(0, 0)]);
+        let [quitter, opponent] = game.players;
+        let mut accounts = [
+            TestAccount::new(quitter, true),
+            TestAccount::game(&program_id, &game),
+        ];
+        process(&program_id, &mut accounts, Instruction::Resign).unwrap();
+        let resigned = Game::load(&accounts[1].data).unwrap();
+        assert_eq!(resigned.state, GameState::Over { winner: opponent });
+        assert!(resigned.forfeited);
+        // the close pays out the resigned game like any other win
+        let (escrow, _) = find_escrow_address(&program_id, &game.stake_mint, None);
+        let (authority, _) = Pubkey::find_program_address(&["authority".as_bytes()], &program_id);
+        let payout = Pubkey::new_unique();
+        let mut accounts = close_accounts(&program_id, &resigned);
+        accounts.push(TestAccount::token(payout, game.stake_mint, opponent, 0));
+        take_cpis();
+        process(&program_id, &mut accounts, close(false, None)).unwrap();
+        assert_eq!(
+            take_cpis(),
+            vec![transfer_instruction(&spl_token::ID, &escrow, &payout, &authority, 200).unwrap()]
+        );
+    }
+
+    #[test]
+    fn only_an_ongoing_game_can_be_resigned() {
+        install_stubs();
+        let program_id = Pubkey::new_unique();
+        let mut game = setup();
+        let player = game.players[0];
+        for (state, error) in [
+            (GameState::Unaccepted, Error::UnacceptedGame),
+            (GameState::Over { winner: player }, Error::GameAlreadyOver),
+            (GameState::Draw, Error::GameAlreadyOver),
+            (GameState::Settled { winner: None }, Error::GameAlreadyOver),
+            (GameState::Cancelled, Error::GameAlreadyOver),
+        ] {
+            game.state = state;
+            game.forfeited = true;
+            let mut accounts = [
+                TestAccount::new(player, true),
+                TestAccount::game(&program_id, &game),
+            ];
+            assert_eq!(
+                process(&program_id, &mut accounts, Instruction::Resign),
+                Err(error.into())
+            );
+        }
+        // nor by someone who isn't playing
+        game.state = GameState::Ongoing;
+        let mut accounts = [
+            TestAccount::new(Pubkey::new_unique(), true),
+            TestAccount::game(&program_id, &game),
+        ];
+        assert_eq!(
+            process(&program_id, &mut accounts, Instruction::Resign),
+            Err(Error::CanNotPlay.into())
+        );
+    }
+
+    #[test]
+    fn a_timeout_is_claimed_by_the_waiting_player_once_it_passed() {
+        install_stubs();
+        let program_id = Pubkey::new_unique();
+        let mut game = setup();
+        game.move_timeout_slots = 10;
+        play_all(&mut game, &[(0, 0)]);
+        let [mover, staller] = game.players;
+        let claim = |game: &Game, player: Pubkey| {
+            let mut accounts = [
+                TestAccount::new(player, true),
+                TestAccount::game(&program_id, game),
+            ];
+            process(&program_id, &mut accounts, Instruction::ClaimTimeout)
+                .map(|()| Game::load(&accounts[1].data).unwrap())
+        };
+        game.last_move_slot = NOW_SLOT - 10;
+        assert_eq!(
+            claim(&game, mover).unwrap_err(),
+            Error::TimeoutNotReached.into()
+        );
+        game.last_move_slot = NOW_SLOT - 11;
+        // the player who has to move can't claim against the other
+        assert_eq!(claim(&game, staller).unwrap_err(), Error::CanNotPlay.into());
+        let claimed = claim(&game, mover).unwrap();
+        assert_eq!(claimed.state, GameState::Over { winner: mover });
+        assert!(claimed.forfeited);
+        assert_eq!(claimed.last_move_slot, NOW_SLOT);
+        // no timeout means the game can't be claimed however long it stalls
+        game.move_timeout_slots = 0;
+        game.last_move_slot = 0;
+        assert_eq!(
+            claim(&game, mover).unwrap_err(),
+            Error::TimeoutNotReached.into()
+        );
+    }
+
+    #[test]
+    fn before_the_first_move_the_timeout_is_claimed_against_the_opener() {
+        install_stubs();
+        let program_id = Pubkey::new_unique();
+        let mut game = setup();
+        game.move_timeout_slots = 10;
+        game.last_move_slot = NOW_SLOT - 11;
+        let [opener, waiting] = game.players;
+        for (player, result) in [(opener, Err(Error::CanNotPlay.into())), (waiting, Ok(()))] {
+            let mut accounts = [
+                TestAccount::new(player, true),
+                TestAccount::game(&program_id, &game),
+            ];
+            assert_eq!(
+                process(&program_id, &mut accounts, Instruction::ClaimTimeout),
+                result
+            );
+        }
+    }
+
+    #[test]
+    fn verify_winning_move_returns_the_resulting_state_without_writing() {
+        install_stubs();
+        let program_id = Pubkey::new_unique();
+        let mut won = setup();
+        let winner = won.players[0];
+        play_all(&mut won, &[(0, 0), (1, 0), (0, 1), (1, 1)]);
+        let mut drawn = setup();
+        play_all(
+            &mut drawn,
+            &[
+                (0, 0),
+                (0, 1),
+                (0, 2),
+                (1, 1),
+                (1, 0),
+                (2, 0),
+                (1, 2),
+                (2, 2),
+            ],
+        );
+        for (game, play, state) in [
+            (won, (0, 2), GameState::Over { winner }),
+            (drawn, (2, 1), GameState::Draw),
+            (setup(), (1, 1), GameState::Ongoing),
+        ] {
+            let mut accounts = [TestAccount::game(&program_id, &game)];
+            let before = accounts[0].data.clone();
+            let (row, col) = play;
+            process(
+                &program_id,
+                &mut accounts,
+                Instruction::VerifyWinningMove(Move { row, col }),
+            )
+            .unwrap();
+            assert_eq!(GameState::try_from_slice(&return_data()).unwrap(), state);
+            assert_eq!(accounts[0].data, before);
+        }
+    }
+}
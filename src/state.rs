@@ -1,16 +1,80 @@
 use crate::error::Error;
 use borsh::{BorshDeserialize, BorshSerialize};
-use solana_program::program_pack::{IsInitialized, Sealed};
-use solana_program::{entrypoint::ProgramResult, pubkey::Pubkey};
+use solana_program::hash::{hashv, Hash};
+use solana_program::program_pack::{IsInitialized, Pack, Sealed};
+use solana_program::{
+    entrypoint::ProgramResult, program_error::ProgramError, pubkey::Pubkey, system_program,
+};
 
-#[derive(BorshSerialize, BorshDeserialize)]
+pub const MAX_EXTRA_STAKES: usize = 2;
+// largest board a game can be created with, the account is sized for it
+pub const MAX_BOARD_SIZE: usize = 5;
+pub const MAX_TILES: usize = MAX_BOARD_SIZE * MAX_BOARD_SIZE;
+// slots the loser of a bonded game has to pay up after the game ended before the winner
+// can slash their bond instead, about a day
+pub const SETTLEMENT_WINDOW_SLOTS: u64 = 216_000;
+
+// only the top left size x size tiles are in play
+pub type Board = [[Option<Symbol>; MAX_BOARD_SIZE]; MAX_BOARD_SIZE];
+
+// the default game is what a zeroed account deserializes to, i.e. not initialized
+#[derive(Clone, Debug, Default, BorshSerialize, BorshDeserialize)]
 pub struct Game {
-    pub players: [Pubkey; 2],
-    pub board: [[Option<Symbol>; 3]; 3],
-    pub state: GameState,
+    // version, turns and state come first so that they sit at fixed offsets
+    pub version: u8,
     pub turns: u8,
+    pub state: GameState,
+    pub players: [Pubkey; 2],
+    pub board: Board,
+    // tiles per side, and how many in a row win
+    pub size: u8,
+    pub win_len: u8,
+    // wins that take a best of n match, 0 or 1 for a single game. wins counts the games
+    // each player has taken so far
+    pub series_target: u8,
+    pub wins: [u8; 2],
+    // row * size + col of every ply in order, Game::NO_MOVE past the last one
+    pub move_history: [u8; MAX_TILES],
     pub stake_mint: Pubkey,
+    // the program owning stake_mint, the legacy token program or token-2022
+    pub token_program: Pubkey,
     pub stake_amount: u64,
+    // what player two stakes, it differs from stake_amount for a handicap bet
+    pub stake_amount_two: u64,
+    // when set only the bond is escrowed and the loser pays the stake on settlement
+    pub bond_amount: u64,
+    // cut of a won pot, in basis points, paid to fee_collector
+    pub fee_bps: u16,
+    pub fee_collector: Pubkey,
+    // the main stake sits in an escrow of its own, so all of its balance (yield included)
+    // belongs to this game
+    pub dedicated_escrow: bool,
+    // what each player actually put into escrow for the main stake
+    pub deposits: [u64; 2],
+    pub join_secret_hash: [u8; 32],
+    pub created_slot: u64,
+    // see Game::derive_id
+    pub game_id: [u8; 32],
+    pub accept_deadline: u64,
+    pub max_duration_slots: u64,
+    // slots a player has for each move before the opponent can claim the game, 0 means unlimited
+    pub move_timeout_slots: u64,
+    pub last_move_slot: u64,
+    pub started_slot: u64,
+    pub allow_early_draw: bool,
+    pub higher_staker_moves_second: bool,
+    // index of the player who moves first and plays X
+    pub first_player: u8,
+    pub misere: bool,
+    // default until the first move is made
+    pub last_mover: Pubkey,
+    // the game was conceded, abandoned or drawn by agreement, so it can be over before any
+    // line was completed or the board filled up
+    pub forfeited: bool,
+    // utf-8, padded with zeros
+    pub label: [u8; 32],
+    pub extra_stakes: [Stake; MAX_EXTRA_STAKES],
+    pub extra_stake_count: u8,
     pub is_initialized: bool,
 }
 
@@ -20,73 +84,1636 @@ impl IsInitialized for Game {
     }
 }
 impl Sealed for Game {}
+// LEN is the borsh size with the largest GameState variant, every field is fixed size otherwise
+impl Pack for Game {
+    const LEN: usize = Game::LEN;
+
+    fn pack_into_slice(&self, dst: &mut [u8]) {
+        self.serialize(&mut &mut dst[..]).unwrap();
+    }
+
+    fn unpack_from_slice(src: &[u8]) -> Result<Self, ProgramError> {
+        Self::load(src)
+    }
+}
+// version 1 of the account: a 3x3 board, a single token program and even stakes, no series
+#[derive(Default, BorshSerialize, BorshDeserialize)]
+struct LegacyGame {
+    version: u8,
+    turns: u8,
+    state: GameState,
+    players: [Pubkey; 2],
+    board: [[Option<Symbol>; 3]; 3],
+    move_history: [u8; 9],
+    stake_mint: Pubkey,
+    stake_amount: u64,
+    bond_amount: u64,
+    fee_bps: u16,
+    fee_collector: Pubkey,
+    dedicated_escrow: bool,
+    deposits: [u64; 2],
+    join_secret_hash: [u8; 32],
+    created_slot: u64,
+    game_id: [u8; 32],
+    accept_deadline: u64,
+    max_duration_slots: u64,
+    move_timeout_slots: u64,
+    last_move_slot: u64,
+    started_slot: u64,
+    allow_early_draw: bool,
+    higher_staker_moves_second: bool,
+    first_player: u8,
+    misere: bool,
+    last_mover: Pubkey,
+    forfeited: bool,
+    label: [u8; 32],
+    extra_stakes: [Stake; MAX_EXTRA_STAKES],
+    extra_stake_count: u8,
+    is_initialized: bool,
+}
+
+impl LegacyGame {
+    const LEN: usize = 1
+        + 1
+        + (1 + 33)
+        + 32 * 2
+        + 9 * 2
+        + 9
+        + 32
+        + 8
+        + 8
+        + 2
+        + 32
+        + 1
+        + 8 * 2
+        + 32
+        + 8
+        + 32
+        + 8 * 5
+        + 1
+        + 1
+        + 1
+        + 1
+        + 32
+        + 1
+        + 32
+        + Stake::LEN * MAX_EXTRA_STAKES
+        + 1
+        + 1;
+}
+
+impl From<LegacyGame> for Game {
+    fn from(legacy: LegacyGame) -> Self {
+        let mut board = Board::default();
+        for (row, tiles) in legacy.board.iter().enumerate() {
+            board[row][..3].copy_from_slice(tiles);
+        }
+        // row * 3 + col is also the index on a 3x3 board of the current layout
+        let mut move_history = [Game::NO_MOVE; MAX_TILES];
+        move_history[..9].copy_from_slice(&legacy.move_history);
+        Self {
+            version: legacy.version,
+            turns: legacy.turns,
+            state: legacy.state,
+            players: legacy.players,
+            board,
+            size: 3,
+            win_len: 3,
+            series_target: 0,
+            wins: [0; 2],
+            move_history,
+            stake_mint: legacy.stake_mint,
+            token_program: spl_token::ID,
+            stake_amount: legacy.stake_amount,
+            stake_amount_two: legacy.stake_amount,
+            bond_amount: legacy.bond_amount,
+            fee_bps: legacy.fee_bps,
+            fee_collector: legacy.fee_collector,
+            dedicated_escrow: legacy.dedicated_escrow,
+            deposits: legacy.deposits,
+            join_secret_hash: legacy.join_secret_hash,
+            created_slot: legacy.created_slot,
+            game_id: legacy.game_id,
+            accept_deadline: legacy.accept_deadline,
+            max_duration_slots: legacy.max_duration_slots,
+            move_timeout_slots: legacy.move_timeout_slots,
+            last_move_slot: legacy.last_move_slot,
+            started_slot: legacy.started_slot,
+            allow_early_draw: legacy.allow_early_draw,
+            higher_staker_moves_second: legacy.higher_staker_moves_second,
+            first_player: legacy.first_player,
+            misere: legacy.misere,
+            last_mover: legacy.last_mover,
+            forfeited: legacy.forfeited,
+            label: legacy.label,
+            extra_stakes: legacy.extra_stakes,
+            extra_stake_count: legacy.extra_stake_count,
+            is_initialized: legacy.is_initialized,
+        }
+    }
+}
+
 impl Game {
-    pub const LEN: usize = 32 * 2 + 9 * 2 + 1 + 32 + 1 + 32 + 8 + 1;
+    // 1 is the 3x3 layout of LegacyGame, 2 sized the board for MAX_BOARD_SIZE
+    pub const VERSION: u8 = 2;
+    pub const NO_MOVE: u8 = u8::MAX;
+    // borsh offsets of the leading fields, everything after state shifts with its variant
+    pub const OFFSET_VERSION: usize = 0;
+    pub const OFFSET_TURNS: usize = Self::OFFSET_VERSION + 1;
+    pub const OFFSET_STATE: usize = Self::OFFSET_TURNS + 1;
+    pub const LEN: usize = 1
+        + 1
+        + (1 + 33)
+        + 32 * 2
+        + MAX_TILES * 2
+        + 1
+        + 1
+        + 1
+        + 2
+        + MAX_TILES
+        + 32
+        + 32
+        + 8
+        + 8
+        + 8
+        + 2
+        + 32
+        + 1
+        + 8 * 2
+        + 32
+        + 8
+        + 32
+        + 8
+        + 8
+        + 8
+        + 8
+        + 8
+        + 1
+        + 1
+        + 1
+        + 1
+        + 32
+        + 1
+        + 32
+        + Stake::LEN * MAX_EXTRA_STAKES
+        + 1
+        + 1;
 
-    pub fn play(&mut self, player: &Pubkey, row: usize, col: usize) -> ProgramResult {
-        if self.state == GameState::Unaccepted {
-            return Err(Error::UnacceptedGame.into());
+    // decodes the account with the layout its version was written in. a legacy game keeps
+    // its version, its account is too small for the current layout so check_version stops
+    // anything from writing it back
+    pub fn load(data: &[u8]) -> Result<Self, ProgramError> {
+        let game = match data.first() {
+            // an uninitialized account is sized for the current layout
+            Some(0) | Some(&Self::VERSION) => {
+                Self::deserialize(&mut &data[..]).map_err(|_| ProgramError::InvalidAccountData)?
+            }
+            Some(1) => LegacyGame::deserialize(&mut &data[..])
+                .map_err(|_| ProgramError::InvalidAccountData)?
+                .into(),
+            Some(_) => return Err(Error::UnsupportedGameVersion.into()),
+            None => return Err(ProgramError::InvalidAccountData),
+        };
+        game.validate_invariants()?;
+        Ok(game)
+    }
+
+    // for clients decoding a fetched account, which has to be a whole and created game of
+    // either layout
+    pub fn from_account_data(data: &[u8]) -> Result<Self, ProgramError> {
+        if data.len() != Self::LEN && data.len() != LegacyGame::LEN {
+            return Err(ProgramError::InvalidAccountData);
         }
-        if self.state != GameState::Ongoing {
-            return Err(Error::GameAlreayOver.into());
+        let game = Self::load(data)?;
+        if !game.is_initialized {
+            return Err(ProgramError::UninitializedAccount);
+        }
+        Ok(game)
+    }
+
+    // turns and state have to agree, anything else is a tampered or corrupted account
+    pub fn validate_invariants(&self) -> ProgramResult {
+        // these index into arrays, so out of range values would panic later on.
+        // a zeroed account has no board yet
+        let board_in_range = !self.is_initialized
+            || ((3..=MAX_BOARD_SIZE as u8).contains(&self.size)
+                && (3..=self.size).contains(&self.win_len));
+        let in_range = self.first_player <= 1
+            && self.extra_stake_count as usize <= MAX_EXTRA_STAKES
+            && self.wins.iter().all(|&wins| wins <= self.series_target)
+            && board_in_range;
+        if !in_range {
+            return Err(ProgramError::InvalidAccountData);
+        }
+        let tiles = self.tiles();
+        // the first line can be completed on X's win_len-th move
+        let first_win = self.win_len.saturating_mul(2).saturating_sub(1);
+        // a draw can be forced or claimed before the board is full
+        let early_draw = self.max_duration_slots != 0 || self.allow_early_draw;
+        let consistent = match self.state {
+            GameState::Unaccepted | GameState::Cancelled => self.turns == 0,
+            GameState::Ongoing => self.turns < tiles,
+            GameState::Over { .. } | GameState::Settled { winner: Some(_) } => {
+                (first_win..=tiles).contains(&self.turns) || (self.forfeited && self.turns < tiles)
+            }
+            GameState::Draw | GameState::Settled { winner: None } => {
+                self.turns == tiles || ((early_draw || self.forfeited) && self.turns < tiles)
+            }
+        };
+        if !consistent {
+            return Err(ProgramError::InvalidAccountData);
+        }
+        Ok(())
+    }
+
+    // clamped so that even a corrupted size can't index past the board
+    fn board_size(&self) -> usize {
+        (self.size as usize).min(MAX_BOARD_SIZE)
+    }
+
+    pub fn tiles(&self) -> u8 {
+        (self.board_size() * self.board_size()) as u8
+    }
+
+    pub fn check_version(&self) -> ProgramResult {
+        if self.version != Self::VERSION {
+            return Err(Error::UnsupportedGameVersion.into());
+        }
+        Ok(())
+    }
+
+    // stakes escrowed on top of the stake_mint/stake_amount pair
+    pub fn basket(&self) -> &[Stake] {
+        &self.extra_stakes[..self.extra_stake_count as usize]
+    }
+
+    // the player who just moved can't be the one stalling the game
+    pub fn is_last_mover(&self, player: &Pubkey) -> bool {
+        self.turns != 0 && self.last_mover == *player
+    }
+
+    // ties keep the creation order
+    pub fn assign_first_player(&mut self, stakes: [u64; 2]) {
+        self.first_player = if self.higher_staker_moves_second && stakes[0] > stakes[1] {
+            1
+        } else {
+            0
+        };
+    }
+
+    pub fn current_player(&self) -> Pubkey {
+        self.players[(self.first_player as usize + self.turns as usize) % 2]
+    }
+
+    // who has to move, none unless the game is ongoing
+    pub fn current_turn_player(&self) -> Option<Pubkey> {
+        (self.state == GameState::Ongoing).then(|| self.current_player())
+    }
+
+    // the symbol on a tile, none for an empty tile or one off the board
+    pub fn board_cell(&self, row: usize, col: usize) -> Option<Symbol> {
+        if row >= self.board_size() || col >= self.board_size() {
+            return None;
+        }
+        self.board[row][col]
+    }
+
+    // stable identifier of a game, reproducible from its players and creation slot.
+    // an open challenge is identified by the default key it was created with
+    pub fn derive_id(players: &[Pubkey; 2], created_slot: u64) -> [u8; 32] {
+        hashv(&[
+            players[0].as_ref(),
+            players[1].as_ref(),
+            &created_slot.to_le_bytes(),
+        ])
+        .to_bytes()
+    }
+
+    pub fn id(&self) -> Hash {
+        Hash::new_from_array(self.game_id)
+    }
+
+    pub fn snapshot(&self) -> GameSnapshot {
+        GameSnapshot {
+            game_id: self.game_id,
+            players: self.players,
+            next_player: self.current_player(),
+            turns: self.turns,
+            state: self.state.clone(),
+            stake_mint: self.stake_mint,
+            stake_amount: self.stake_amount,
+            board: self.board,
+            size: self.size,
+            win_len: self.win_len,
+            misere: self.misere,
+        }
+    }
+
+    // a game to predict moves on client side, the players, stake and game id come from the
+    // snapshot too. settlement fields (deposits, deadlines, basket) are left at their defaults
+    pub fn from_snapshot(snapshot: &GameSnapshot) -> Result<Self, ProgramError> {
+        let first_player = if snapshot.players[snapshot.turns as usize % 2] == snapshot.next_player
+        {
+            0
+        } else {
+            1
+        };
+        let mut game = Self {
+            version: Self::VERSION,
+            turns: snapshot.turns,
+            state: snapshot.state.clone(),
+            players: snapshot.players,
+            board: snapshot.board,
+            size: snapshot.size,
+            win_len: snapshot.win_len,
+            stake_mint: snapshot.stake_mint,
+            stake_amount: snapshot.stake_amount,
+            game_id: snapshot.game_id,
+            first_player,
+            misere: snapshot.misere,
+            // the order of the moves is not part of the snapshot
+            move_history: [Self::NO_MOVE; MAX_TILES],
+            is_initialized: true,
+            ..Default::default()
+        };
+        if game.turns != 0 {
+            game.last_mover = game.opponent(&snapshot.next_player);
+        }
+        // not carried by the snapshot, a result without a full board or a line explains them
+        let finished_early = !game.has_line(&game.board) && game.turns < game.tiles();
+        game.forfeited = matches!(game.state, GameState::Over { .. }) && finished_early;
+        game.allow_early_draw = game.state == GameState::Draw && finished_early;
+        if game
+            .board
+            .iter()
+            .flatten()
+            .filter(|tile| tile.is_some())
+            .count()
+            != game.turns as usize
+        {
+            return Err(ProgramError::InvalidAccountData);
+        }
+        game.validate_invariants()?;
+        Ok(game)
+    }
+
+    // payouts scaled to what the game's escrow actually holds, the last recipient
+    // gets whatever rounding leaves over
+    pub fn payouts_from_balance(&self, balance: u64) -> Vec<(Pubkey, u64)> {
+        let mut payouts = self.payouts();
+        let expected: u128 = payouts.iter().map(|&(_, amount)| amount as u128).sum();
+        if expected == 0 {
+            return payouts;
+        }
+        let last = payouts.len() - 1;
+        let mut remaining = balance;
+        for (i, (_, amount)) in payouts.iter_mut().enumerate() {
+            if i == last {
+                *amount = remaining;
+            } else {
+                *amount = (*amount as u128 * balance as u128 / expected) as u64;
+                remaining -= *amount;
+            }
         }
-        if self.players[(self.turns % 2) as usize] != *player {
+        payouts
+    }
+
+    // what each player stakes, by player index
+    pub fn stakes(&self) -> [u64; 2] {
+        [self.stake_amount, self.stake_amount_two]
+    }
+
+    // what a player holds in escrow for the main stake
+    pub fn escrowed_amount(&self, player: usize) -> u64 {
+        if self.bond_amount != 0 {
+            self.bond_amount
+        } else {
+            self.stakes()[player]
+        }
+    }
+
+    // the house's cut of a pot, rounded down so that the winner gets the remainder
+    pub fn fee(&self, pot: u64) -> u64 {
+        (pot as u128 * self.fee_bps as u128 / 10_000) as u64
+    }
+
+    // (recipient, amount) of the main stake for a finished game, empty otherwise.
+    // a bonded game gives both bonds back, the stake itself is paid by the loser.
+    // a win pays the fee collector first when the game takes a fee, draws are fee free
+    pub fn payouts(&self) -> Vec<(Pubkey, u64)> {
+        let refunds = vec![
+            (self.players[0], self.deposits[0]),
+            (self.players[1], self.deposits[1]),
+        ];
+        match self.state {
+            GameState::Over { .. } if self.bond_amount != 0 => refunds,
+            GameState::Over { winner } => {
+                let pot = self.deposits[0].saturating_add(self.deposits[1]);
+                if self.fee_bps == 0 {
+                    return vec![(winner, pot)];
+                }
+                let fee = self.fee(pot);
+                vec![(self.fee_collector, fee), (winner, pot - fee)]
+            }
+            GameState::Draw => refunds,
+            _ => Vec::new(),
+        }
+    }
+
+    pub fn label(&self) -> Result<&str, ProgramError> {
+        let len = self
+            .label
+            .iter()
+            .rposition(|&byte| byte != 0)
+            .map_or(0, |last| last + 1);
+        std::str::from_utf8(&self.label[..len]).map_err(|_| Error::InvalidLabel.into())
+    }
+
+    // the system program id as stake mint marks a game staking lamports instead of tokens
+    pub fn is_native(&self) -> bool {
+        self.stake_mint == system_program::ID
+    }
+
+    // a casual game stakes nothing, it takes the lamport layout but never gets an escrow
+    pub fn is_casual(&self) -> bool {
+        self.is_native() && self.stake_amount == 0 && self.stake_amount_two == 0
+    }
+
+    pub fn play(&mut self, player: &Pubkey, row: usize, col: usize) -> ProgramResult {
+        match self.state {
+            GameState::Ongoing => {}
+            GameState::Unaccepted => return Err(Error::UnacceptedGame.into()),
+            GameState::Over { .. } | GameState::Draw => return Err(Error::GameAlreadyOver.into()),
+            GameState::Settled { .. } => return Err(Error::AlreadySettled.into()),
+            GameState::Cancelled => return Err(Error::GameCancelled.into()),
+        }
+        if !self.players.contains(player) {
             return Err(Error::CanNotPlay.into());
         }
-        if row > 3 || col > 3 {
+        if self.current_player() != *player {
+            return Err(Error::NotYourTurn.into());
+        }
+        if row >= self.board_size() || col >= self.board_size() {
             return Err(Error::InvalidTileSelected.into());
         }
-        if let Some(_) = self.board[row][col] {
+        if self.board[row][col].is_some() {
             return Err(Error::TileOccupied.into());
         }
-        let symbol = if self.turns % 2 == 0 {
-            Symbol::X
-        } else {
-            Symbol::O
-        };
-        self.board[row][col] = Some(symbol);
-        self.turns += 1;
-        self.update_state(player);
+        let turns = self.turns.checked_add(1).ok_or(Error::Overflow)?;
+        if self.turns as usize >= self.move_history.len() {
+            return Err(ProgramError::InvalidAccountData);
+        }
+        self.board[row][col] = Some(self.current_symbol());
+        self.move_history[self.turns as usize] = (row * self.board_size() + col) as u8;
+        self.turns = turns;
+        self.last_mover = *player;
+        self.update_state();
 
         Ok(())
     }
-    fn update_state(&mut self, player: &Pubkey) {
-        for i in 0..3 {
-            if let Some(_) = self.board[i][0] {
-                if self.board[i][0] == self.board[i][1] && self.board[i][0] == self.board[i][2] {
-                    self.state = GameState::Over { winner: *player };
-                    return;
+    // plays the move for whoever's turn it is
+    pub fn apply_move(&mut self, play: Move) -> ProgramResult {
+        let player = self.current_player();
+        self.play(&player, play.row as usize, play.col as usize)
+    }
+    // a fresh board for the next game, the players swap who opens
+    pub fn reset_board(&mut self) {
+        self.board = [[None; MAX_BOARD_SIZE]; MAX_BOARD_SIZE];
+        self.move_history = [Self::NO_MOVE; MAX_TILES];
+        self.turns = 0;
+        self.last_mover = Pubkey::default();
+        self.forfeited = false;
+        self.first_player ^= 1;
+        self.state = GameState::Ongoing;
+    }
+    // in a series a game won on the board scores for the winner and, unless that takes the
+    // match, the next game starts right away. a full board is replayed without a score.
+    // returns the finished game's state when the board was reset
+    pub fn next_round(&mut self) -> Option<GameState> {
+        if self.series_target <= 1 {
+            return None;
+        }
+        let round = self.state.clone();
+        match round {
+            GameState::Over { winner } => {
+                let i = self.players.iter().position(|player| *player == winner)?;
+                self.wins[i] = self.wins[i].saturating_add(1);
+                if self.wins[i] >= self.series_target {
+                    return None;
                 }
             }
-            if let Some(_) = self.board[0][i] {
-                if self.board[0][i] == self.board[1][i] && self.board[0][i] == self.board[2][i] {
-                    self.state = GameState::Over { winner: *player };
-                    return;
+            GameState::Draw => {}
+            _ => return None,
+        }
+        self.reset_board();
+        Some(round)
+    }
+    // one line per row, X and O for the symbols and - for an empty tile
+    pub fn board_rows(&self) -> Vec<String> {
+        self.board
+            .iter()
+            .take(self.board_size())
+            .map(|row| {
+                row[..self.board_size()]
+                    .iter()
+                    .map(|tile| match tile {
+                        Some(Symbol::X) => 'X',
+                        Some(Symbol::O) => 'O',
+                        None => '-',
+                    })
+                    .collect()
+            })
+            .collect()
+    }
+    // the moves played so far in order, for replaying a game
+    pub fn history(&self) -> Vec<Move> {
+        let size = self.board_size() as u8;
+        if size == 0 {
+            return Vec::new();
+        }
+        self.move_history
+            .iter()
+            .take_while(|&&tile| tile != Self::NO_MOVE)
+            .map(|&tile| Move {
+                row: tile / size,
+                col: tile % size,
+            })
+            .collect()
+    }
+
+    // the state the game would be in after the current player's move, the game itself is untouched
+    pub fn preview_move(&self, play: Move) -> Result<GameState, ProgramError> {
+        let mut game = self.clone();
+        game.apply_move(play)?;
+        Ok(game.state)
+    }
+    // in misere completing a line loses, so no move of the player's own ever wins there
+    pub fn is_winning_move(&self, row: usize, col: usize) -> bool {
+        if self.misere {
+            return false;
+        }
+        if self.state != GameState::Ongoing || row >= self.board_size() || col >= self.board_size()
+        {
+            return false;
+        }
+        if self.board[row][col].is_some() {
+            return false;
+        }
+        let mut board = self.board;
+        board[row][col] = Some(self.current_symbol());
+        self.has_line(&board)
+    }
+    // empty tiles the current player can pick, none once the game is no longer ongoing
+    pub fn legal_moves(&self) -> Vec<Move> {
+        if self.state != GameState::Ongoing {
+            return Vec::new();
+        }
+        let mut moves = Vec::new();
+        for row in 0..self.board_size() {
+            for col in 0..self.board_size() {
+                if self.board[row][col].is_none() {
+                    moves.push(Move {
+                        row: row as u8,
+                        col: col as u8,
+                    });
                 }
             }
         }
-        if self.turns == 9 {
+        moves
+    }
+    // a game is provably drawn once every line holds both symbols
+    pub fn is_dead_draw(&self) -> bool {
+        self.lines().iter().all(|line| {
+            let has = |symbol| {
+                line.iter()
+                    .any(|&(row, col)| self.board[row][col] == Some(symbol))
+            };
+            has(Symbol::X) && has(Symbol::O)
+        })
+    }
+    // (x_can_win, o_can_win): whether the side still has a line free of the other
+    // symbol that it can fill with the moves it has left. in misere a side wins when the
+    // other one fills a line, so the two are swapped.
+    pub fn outcome_still_possible(&self) -> (bool, bool) {
+        if !matches!(self.state, GameState::Unaccepted | GameState::Ongoing) {
+            return (false, false);
+        }
+        // X makes the odd moves, O the even ones, so X gets the extra move on an odd board
+        let tiles = self.tiles() as usize;
+        let o_moves_made = self.turns as usize / 2;
+        let x_moves_made = self.turns as usize - o_moves_made;
+        let o_moves_total = tiles / 2;
+        let x_moves_left = (tiles - o_moves_total).saturating_sub(x_moves_made);
+        let o_moves_left = o_moves_total.saturating_sub(o_moves_made);
+        let lines = self.lines();
+        let can_win = |symbol: Symbol, moves_left: usize| {
+            lines.iter().any(|line| {
+                let mut empty = 0;
+                for &(row, col) in line {
+                    match self.board[row][col] {
+                        Some(s) if s != symbol => return false,
+                        Some(_) => {}
+                        None => empty += 1,
+                    }
+                }
+                empty <= moves_left
+            })
+        };
+        let x_completes = can_win(Symbol::X, x_moves_left);
+        let o_completes = can_win(Symbol::O, o_moves_left);
+        if self.misere {
+            (o_completes, x_completes)
+        } else {
+            (x_completes, o_completes)
+        }
+    }
+    pub fn current_symbol(&self) -> Symbol {
+        if self.turns & 1 == 0 {
+            Symbol::X
+        } else {
+            Symbol::O
+        }
+    }
+    // the line, if any, was completed by the player who just moved
+    fn update_state(&mut self) {
+        if self.has_line(&self.board) {
+            // in misere the player completing a line loses
+            let winner = if self.misere {
+                self.opponent(&self.last_mover)
+            } else {
+                self.last_mover
+            };
+            self.state = GameState::Over { winner };
+            return;
+        }
+        if self.turns == self.tiles() {
             self.state = GameState::Draw;
         }
     }
+    pub fn opponent(&self, player: &Pubkey) -> Pubkey {
+        if self.players[0] == *player {
+            self.players[1]
+        } else {
+            self.players[0]
+        }
+    }
+    // every run of win_len tiles along a row, a column or either diagonal direction
+    pub fn lines(&self) -> Vec<Vec<(usize, usize)>> {
+        let size = self.board_size() as isize;
+        let len = self.win_len as isize;
+        let mut lines = Vec::new();
+        if len == 0 {
+            return lines;
+        }
+        for (row_step, col_step) in [(0, 1), (1, 0), (1, 1), (1, -1)] {
+            for row in 0..size {
+                for col in 0..size {
+                    let last_row = row + row_step * (len - 1);
+                    let last_col = col + col_step * (len - 1);
+                    if last_row < size && (0..size).contains(&last_col) {
+                        lines.push(
+                            (0..len)
+                                .map(|i| {
+                                    ((row + row_step * i) as usize, (col + col_step * i) as usize)
+                                })
+                                .collect(),
+                        );
+                    }
+                }
+            }
+        }
+        lines
+    }
+    fn has_line(&self, board: &Board) -> bool {
+        self.lines().iter().any(|line| {
+            let (row, col) = line[0];
+            board[row][col].is_some() && line.iter().all(|&(r, c)| board[r][c] == board[row][col])
+        })
+    }
+}
+
+// what a client needs to follow a game without decoding the whole account
+#[derive(Debug, PartialEq, BorshSerialize, BorshDeserialize)]
+pub struct GameSnapshot {
+    pub game_id: [u8; 32],
+    pub players: [Pubkey; 2],
+    pub next_player: Pubkey,
+    pub turns: u8,
+    pub state: GameState,
+    pub stake_mint: Pubkey,
+    pub stake_amount: u64,
+    pub board: Board,
+    pub size: u8,
+    pub win_len: u8,
+    pub misere: bool,
+}
+
+#[derive(Copy, Clone, Debug, Default, PartialEq, BorshSerialize, BorshDeserialize)]
+pub struct Stake {
+    pub mint: Pubkey,
+    pub amount: u64,
+}
+
+impl Stake {
+    pub const LEN: usize = 32 + 8;
+}
+
+// one account for the whole program, so every create_game, close_game and slash_bond
+// write locks it and those transactions can't run in parallel. fine at the volume a game
+// sees, sharding it (e.g. by stake mint) would be the way out, at the cost of summing the
+// shards off chain
+#[derive(BorshSerialize, BorshDeserialize)]
+pub struct GlobalStats {
+    pub games_created: u64,
+    pub games_settled: u64,
+    pub total_staked: u64,
+    pub is_initialized: bool,
+}
+
+impl IsInitialized for GlobalStats {
+    fn is_initialized(&self) -> bool {
+        self.is_initialized
+    }
+}
+impl Sealed for GlobalStats {}
+impl GlobalStats {
+    pub const LEN: usize = 8 + 8 + 8 + 1;
+
+    pub fn load(data: &[u8]) -> Result<Self, ProgramError> {
+        Self::deserialize(&mut &data[..]).map_err(|_| ProgramError::InvalidAccountData)
+    }
+}
+
+impl Game {
+    const EXPORT_VERSION: u8 = 2;
+
+    // compact archive format independent of the account layout: export version, players,
+    // board size, win length, base 3 packed board, turns, first player, misere, stake and state
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = vec![Self::EXPORT_VERSION];
+        bytes.extend_from_slice(self.players[0].as_ref());
+        bytes.extend_from_slice(self.players[1].as_ref());
+        bytes.push(self.size);
+        bytes.push(self.win_len);
+        bytes.extend_from_slice(&pack_board(&self.board, self.board_size()).to_le_bytes());
+        bytes.push(self.turns);
+        bytes.push(self.first_player);
+        bytes.push(self.misere as u8);
+        bytes.extend_from_slice(self.stake_mint.as_ref());
+        bytes.extend_from_slice(&self.stake_amount.to_le_bytes());
+        self.state.serialize(&mut bytes).unwrap();
+        bytes
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, ProgramError> {
+        let data = &mut &bytes[..];
+        if u8::deserialize(data)? != Self::EXPORT_VERSION {
+            return Err(Error::UnsupportedGameVersion.into());
+        }
+        let players = [Pubkey::deserialize(data)?, Pubkey::deserialize(data)?];
+        let size = u8::deserialize(data)?;
+        if size as usize > MAX_BOARD_SIZE {
+            return Err(ProgramError::InvalidAccountData);
+        }
+        let game = Self {
+            version: Self::VERSION,
+            players,
+            size,
+            win_len: u8::deserialize(data)?,
+            board: unpack_board(u64::deserialize(data)?, size as usize)?,
+            turns: u8::deserialize(data)?,
+            first_player: u8::deserialize(data)?,
+            misere: bool::deserialize(data)?,
+            stake_mint: Pubkey::deserialize(data)?,
+            stake_amount: u64::deserialize(data)?,
+            state: GameState::deserialize(data)?,
+            // the export only carries the board
+            move_history: [Self::NO_MOVE; MAX_TILES],
+            is_initialized: true,
+            ..Default::default()
+        };
+        if !data.is_empty() || game.first_player > 1 {
+            return Err(ProgramError::InvalidAccountData);
+        }
+        game.validate_invariants()?;
+        Ok(game)
+    }
 }
 
-#[derive(Copy, Clone, PartialEq, BorshSerialize, BorshDeserialize)]
+// one base 3 digit per tile in play, row by row: 0 empty, 1 X, 2 O
+fn pack_board(board: &Board, size: usize) -> u64 {
+    let tiles = board.iter().take(size).flat_map(|row| &row[..size]);
+    tiles.rev().fold(0, |packed, tile| {
+        let digit = match tile {
+            None => 0,
+            Some(Symbol::X) => 1,
+            Some(Symbol::O) => 2,
+        };
+        packed * 3 + digit
+    })
+}
+
+fn unpack_board(mut packed: u64, size: usize) -> Result<Board, ProgramError> {
+    let mut board = [[None; MAX_BOARD_SIZE]; MAX_BOARD_SIZE];
+    for tile in board.iter_mut().take(size).flat_map(|row| &mut row[..size]) {
+        *tile = match packed % 3 {
+            0 => None,
+            1 => Some(Symbol::X),
+            _ => Some(Symbol::O),
+        };
+        packed /= 3;
+    }
+    if packed != 0 {
+        return Err(ProgramError::InvalidAccountData);
+    }
+    Ok(board)
+}
+
+#[cfg(not(target_os = "solana"))]
+impl Game {
+    // hand-rolled so that no json dependency ends up in the on-chain binary
+    pub fn to_json(&self) -> String {
+        let board = self
+            .board_rows()
+            .iter()
+            .map(|row| format!("\"{}\"", row))
+            .collect::<Vec<_>>()
+            .join(",");
+        let state = match self.state {
+            GameState::Unaccepted => "{\"status\":\"unaccepted\"}".to_string(),
+            GameState::Ongoing => "{\"status\":\"ongoing\"}".to_string(),
+            GameState::Over { winner } => {
+                format!("{{\"status\":\"over\",\"winner\":\"{}\"}}", winner)
+            }
+            GameState::Draw => "{\"status\":\"draw\"}".to_string(),
+            GameState::Settled {
+                winner: Some(winner),
+            } => {
+                format!("{{\"status\":\"settled\",\"winner\":\"{}\"}}", winner)
+            }
+            GameState::Settled { winner: None } => "{\"status\":\"settled\"}".to_string(),
+            GameState::Cancelled => "{\"status\":\"cancelled\"}".to_string(),
+        };
+        format!(
+            "{{\"id\":\"{}\",\"players\":[\"{}\",\"{}\"],\"board\":[{}],\"state\":{},\"label\":\"{}\",\"turns\":{},\"created_slot\":{},\"stake\":{{\"mint\":\"{}\",\"amount\":{}}}}}",
+            self.id(),
+            self.players[0],
+            self.players[1],
+            board,
+            state,
+            self.label()
+                .unwrap_or_default()
+                .replace('\\', "\\\\")
+                .replace('"', "\\\""),
+            self.turns,
+            self.created_slot,
+            self.stake_mint,
+            self.stake_amount
+        )
+    }
+}
+
+// reads (turns, state discriminant) without decoding the whole game, every layout keeps
+// the leading fields in place
+pub fn decode_turn_info(data: &[u8]) -> Result<(u8, u8), ProgramError> {
+    if data.len() <= Game::OFFSET_STATE {
+        return Err(ProgramError::InvalidAccountData);
+    }
+    if !(1..=Game::VERSION).contains(&data[Game::OFFSET_VERSION]) {
+        return Err(Error::UnsupportedGameVersion.into());
+    }
+    Ok((data[Game::OFFSET_TURNS], data[Game::OFFSET_STATE]))
+}
+
+// replays the moves on a fresh ongoing classic 3x3 game, without any account context
+pub fn simulate(players: [Pubkey; 2], moves: &[(u8, u8)]) -> Result<Game, ProgramError> {
+    let mut game = Game {
+        version: Game::VERSION,
+        players,
+        state: GameState::Ongoing,
+        size: 3,
+        win_len: 3,
+        move_history: [Game::NO_MOVE; MAX_TILES],
+        is_initialized: true,
+        ..Default::default()
+    };
+    for &(row, col) in moves {
+        game.apply_move(Move { row, col })?;
+    }
+    Ok(game)
+}
+
+pub fn board_diff(before: &Board, after: &Board) -> Vec<(u8, u8, Symbol)> {
+    let mut diff = Vec::new();
+    for row in 0..MAX_BOARD_SIZE {
+        for col in 0..MAX_BOARD_SIZE {
+            if let Some(symbol) = after[row][col] {
+                if before[row][col] != Some(symbol) {
+                    diff.push((row as u8, col as u8, symbol));
+                }
+            }
+        }
+    }
+    diff
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, BorshSerialize, BorshDeserialize)]
+pub struct Move {
+    pub row: u8,
+    pub col: u8,
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, BorshSerialize, BorshDeserialize)]
 pub enum Symbol {
     X,
     O,
 }
 
-#[derive(PartialEq, BorshSerialize, BorshDeserialize)]
+#[derive(Clone, Debug, Default, PartialEq, BorshSerialize, BorshDeserialize)]
 pub enum GameState {
+    #[default]
     Unaccepted,
     Ongoing,
-    Over { winner: Pubkey },
+    Over {
+        winner: Pubkey,
+    },
     Draw,
+    // settled game kept alive as a trophy, winner is None for a draw
+    Settled {
+        winner: Option<Pubkey>,
+    },
+    // refunded before it was accepted, written just before the account is closed
+    Cancelled,
 }
 
-impl Default for GameState {
-    fn default() -> Self {
-        Self::Unaccepted
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+
+    // an accepted classic game with both stakes escrowed, player one opens
+    pub(crate) fn setup() -> Game {
+        let players = [Pubkey::new_unique(), Pubkey::new_unique()];
+        let mut game = simulate(players, &[]).unwrap();
+        game.stake_mint = Pubkey::new_unique();
+        game.stake_amount = 100;
+        game.stake_amount_two = 100;
+        game.deposits = [100, 100];
+        game
+    }
+
+    pub(crate) fn play_all(game: &mut Game, moves: &[(u8, u8)]) {
+        for &(row, col) in moves {
+            game.apply_move(Move { row, col }).unwrap();
+        }
+    }
+
+    #[test]
+    fn lifecycle_pays_the_pot_to_the_winner() {
+        let mut game = setup();
+        let winner = game.players[0];
+        play_all(&mut game, &[(0, 0), (1, 0), (0, 1), (1, 1)]);
+        assert_eq!(game.state, GameState::Ongoing);
+        assert!(game.payouts().is_empty());
+        play_all(&mut game, &[(0, 2)]);
+        assert_eq!(game.state, GameState::Over { winner });
+        assert_eq!(game.payouts(), vec![(winner, 200)]);
+        game.validate_invariants().unwrap();
+    }
+
+    #[test]
+    fn only_the_player_who_just_moved_is_the_last_mover() {
+        let mut game = setup();
+        let [one, two] = game.players;
+        assert!(!game.is_last_mover(&one) && !game.is_last_mover(&two));
+        play_all(&mut game, &[(0, 0)]);
+        assert!(game.is_last_mover(&one) && !game.is_last_mover(&two));
+        play_all(&mut game, &[(1, 1)]);
+        assert!(!game.is_last_mover(&one) && game.is_last_mover(&two));
+        game.reset_board();
+        assert!(!game.is_last_mover(&one) && !game.is_last_mover(&two));
+    }
+
+    #[test]
+    fn misere_turns_completing_a_line_into_a_loss() {
+        let mut game = setup();
+        // X holds two of the top row, X to move
+        play_all(&mut game, &[(0, 0), (1, 0), (0, 1), (1, 1)]);
+        assert!(game.is_winning_move(0, 2));
+        game.misere = true;
+        assert!(!game.is_winning_move(0, 2));
+        play_all(&mut game, &[(0, 2)]);
+        assert_eq!(
+            game.state,
+            GameState::Over {
+                winner: game.players[1]
+            }
+        );
+
+        // only O can still fill a line, the main diagonal, which loses it the misere game
+        let mut game = setup();
+        play_all(&mut game, &[(1, 2), (1, 1), (1, 0), (0, 2), (0, 1), (0, 0)]);
+        assert_eq!(game.outcome_still_possible(), (false, true));
+        game.misere = true;
+        assert_eq!(game.outcome_still_possible(), (true, false));
+    }
+
+    #[test]
+    fn legacy_3x3_account_decodes_into_the_current_layout() {
+        let mut board = [[None; 3]; 3];
+        board[0][0] = Some(Symbol::X);
+        board[1][1] = Some(Symbol::O);
+        let mut move_history = [Game::NO_MOVE; 9];
+        move_history[..2].copy_from_slice(&[0, 4]);
+        let legacy = LegacyGame {
+            version: 1,
+            turns: 2,
+            state: GameState::Ongoing,
+            players: [Pubkey::new_unique(), Pubkey::new_unique()],
+            board,
+            move_history,
+            stake_amount: 100,
+            deposits: [100, 100],
+            is_initialized: true,
+            ..LegacyGame::default()
+        };
+        // accounts were sized for the largest state and board
+        let mut data = legacy.try_to_vec().unwrap();
+        assert!(data.len() <= LegacyGame::LEN);
+        data.resize(LegacyGame::LEN, 0);
+
+        let game = Game::from_account_data(&data).unwrap();
+        assert_eq!((game.version, game.size, game.win_len), (1, 3, 3));
+        assert_eq!(game.players, legacy.players);
+        assert_eq!(game.board_cell(0, 0), Some(Symbol::X));
+        assert_eq!(game.board_cell(1, 1), Some(Symbol::O));
+        assert_eq!(
+            game.history(),
+            vec![Move { row: 0, col: 0 }, Move { row: 1, col: 1 }]
+        );
+        assert_eq!(game.stakes(), [100, 100]);
+        assert_eq!(game.legal_moves().len(), 7);
+        // the account is too small to be written back in the current layout
+        assert_eq!(
+            game.check_version(),
+            Err(Error::UnsupportedGameVersion.into())
+        );
+    }
+
+    #[test]
+    fn current_4x4_account_decodes() {
+        let mut game = setup();
+        game.size = 4;
+        game.win_len = 4;
+        let winner = game.players[0];
+        play_all(
+            &mut game,
+            &[(0, 0), (1, 0), (0, 1), (1, 1), (0, 2), (1, 2), (0, 3)],
+        );
+        assert_eq!(game.state, GameState::Over { winner });
+        let mut data = vec![0; Game::LEN];
+        Game::pack(game.clone(), &mut data).unwrap();
+
+        let decoded = Game::from_account_data(&data).unwrap();
+        assert_eq!((decoded.version, decoded.size, decoded.win_len), (2, 4, 4));
+        assert_eq!(decoded.board, game.board);
+        assert_eq!(decoded.history(), game.history());
+        assert_eq!(decoded.state, GameState::Over { winner });
+        decoded.check_version().unwrap();
+    }
+
+    #[test]
+    fn account_round_trips_through_pack() {
+        let mut game = setup();
+        play_all(&mut game, &[(1, 1), (0, 0)]);
+        let mut data = vec![0; Game::LEN];
+        Game::pack(game.clone(), &mut data).unwrap();
+        let unpacked = Game::unpack(&data).unwrap();
+        assert_eq!(unpacked.board, game.board);
+        assert_eq!(unpacked.history(), game.history());
+        assert_eq!(unpacked.deposits, [100, 100]);
+    }
+
+    #[test]
+    fn both_diagonals_win() {
+        for moves in [
+            [(0, 0), (0, 1), (1, 1), (0, 2), (2, 2)],
+            [(0, 2), (0, 1), (1, 1), (0, 0), (2, 0)],
+        ] {
+            let mut game = setup();
+            play_all(&mut game, &moves);
+            assert_eq!(
+                game.state,
+                GameState::Over {
+                    winner: game.players[0]
+                }
+            );
+        }
+    }
+
+    #[test]
+    fn the_player_completing_the_line_is_the_winner() {
+        let mut game = setup();
+        play_all(&mut game, &[(0, 0), (1, 0), (0, 1), (1, 1), (2, 2), (1, 2)]);
+        assert_eq!(
+            game.state,
+            GameState::Over {
+                winner: game.players[1]
+            }
+        );
+
+        // the symbol follows whoever opened, not the seat
+        let mut game = setup();
+        game.first_player = 1;
+        play_all(&mut game, &[(0, 0), (1, 0), (0, 1), (1, 1), (0, 2)]);
+        assert_eq!(
+            game.state,
+            GameState::Over {
+                winner: game.players[1]
+            }
+        );
+    }
+
+    #[test]
+    fn only_the_player_to_move_can_play() {
+        let mut game = setup();
+        let [one, two] = game.players;
+        assert_eq!(game.play(&two, 0, 0), Err(Error::NotYourTurn.into()));
+        game.play(&one, 0, 0).unwrap();
+        assert_eq!(game.play(&one, 1, 1), Err(Error::NotYourTurn.into()));
+        game.play(&two, 1, 1).unwrap();
+        assert_eq!(game.turns, 2);
+    }
+
+    #[test]
+    fn tiles_past_the_board_are_rejected() {
+        let mut game = setup();
+        let player = game.players[0];
+        for (row, col) in [(3, 0), (0, 3), (4, 4)] {
+            assert_eq!(
+                game.play(&player, row, col),
+                Err(Error::InvalidTileSelected.into())
+            );
+        }
+        assert_eq!(game.turns, 0);
+    }
+
+    // xorshift64, enough to drive the fuzz loop deterministically without a fuzzing crate
+    struct Rng(u64);
+
+    impl Rng {
+        fn next(&mut self) -> u64 {
+            self.0 ^= self.0 << 13;
+            self.0 ^= self.0 >> 7;
+            self.0 ^= self.0 << 17;
+            self.0
+        }
+
+        fn below(&mut self, n: u64) -> u64 {
+            self.next() % n
+        }
+    }
+
+    // fixed seeds so that a failure reproduces, the seed of any new failure goes here too
+    const SEED_CORPUS: [u64; 6] = [1, 7, 0xdead_beef, 0x1234_5678_9abc_def0, u64::MAX, 42];
+
+    // hand picked sequences: out of range tiles, replays of a tile, moves after the end
+    const MOVE_CORPUS: [&[(usize, usize)]; 3] = [
+        &[(3, 0), (0, 3), (5, 5), (usize::MAX, 0)],
+        &[(0, 0), (0, 0), (1, 1), (1, 1)],
+        &[(0, 0), (1, 0), (0, 1), (1, 1), (0, 2), (2, 2), (2, 1)],
+    ];
+
+    fn fuzz_game(rng: &mut Rng) -> Game {
+        let mut game = setup();
+        game.size = 3 + rng.below(MAX_BOARD_SIZE as u64 - 2) as u8;
+        game.win_len = 3 + rng.below(game.size as u64 - 2) as u8;
+        game.misere = rng.below(2) == 1;
+        game.first_player = rng.below(2) as u8;
+        game
+    }
+
+    #[test]
+    fn fuzz_moves_never_panic_and_keep_the_invariants() {
+        let stranger = Pubkey::new_unique();
+        for seed in SEED_CORPUS {
+            let mut rng = Rng(seed);
+            for _ in 0..200 {
+                let mut game = fuzz_game(&mut rng);
+                for _ in 0..40 {
+                    let row = rng.below(MAX_BOARD_SIZE as u64 + 2) as usize;
+                    let col = rng.below(MAX_BOARD_SIZE as u64 + 2) as usize;
+                    let before = game.turns;
+                    let result = match rng.below(4) {
+                        0 => game.play(&stranger, row, col),
+                        1 => {
+                            let player = game.players[rng.below(2) as usize];
+                            game.play(&player, row, col)
+                        }
+                        _ => game.apply_move(Move {
+                            row: row as u8,
+                            col: col as u8,
+                        }),
+                    };
+                    // a rejected move leaves the game untouched
+                    if result.is_err() {
+                        assert_eq!(game.turns, before);
+                    }
+                    game.validate_invariants().unwrap();
+                }
+            }
+        }
+        for moves in MOVE_CORPUS {
+            let mut game = setup();
+            for &(row, col) in moves {
+                let player = game.current_player();
+                let _ = game.play(&player, row, col);
+                game.validate_invariants().unwrap();
+            }
+        }
+    }
+
+    #[test]
+    fn json_has_the_board_rows_and_an_escaped_label() {
+        let mut game = setup();
+        play_all(&mut game, &[(0, 0), (1, 1)]);
+        game.label[..5].copy_from_slice(b"a \"b\"");
+        let json = game.to_json();
+        assert!(json.contains("\"board\":[\"X--\",\"-O-\",\"---\"]"));
+        assert!(json.contains("\"state\":{\"status\":\"ongoing\"}"));
+        assert!(json.contains("\"label\":\"a \\\"b\\\"\""));
+        assert!(json.contains("\"turns\":2"));
+    }
+
+    #[test]
+    fn board_diff_lists_the_new_symbols_only() {
+        let mut game = setup();
+        play_all(&mut game, &[(0, 0), (1, 1)]);
+        let before = game.board;
+        play_all(&mut game, &[(2, 2), (0, 2)]);
+        assert_eq!(
+            board_diff(&before, &game.board),
+            vec![(0, 2, Symbol::O), (2, 2, Symbol::X)]
+        );
+        assert!(board_diff(&game.board, &game.board).is_empty());
+    }
+
+    #[test]
+    fn a_winning_move_is_only_spotted_for_the_player_to_move() {
+        let mut game = setup();
+        // X holds (0, 0) and (0, 1), O holds (1, 0) and (1, 1), X to move
+        play_all(&mut game, &[(0, 0), (1, 0), (0, 1), (1, 1)]);
+        assert!(game.is_winning_move(0, 2));
+        // O's own line is not X's win, taken and off board tiles never win
+        for (row, col) in [(1, 2), (2, 2), (0, 0), (3, 2)] {
+            assert!(!game.is_winning_move(row, col));
+        }
+        // nothing wins once the game is over
+        play_all(&mut game, &[(0, 2)]);
+        assert!(!game.is_winning_move(2, 2));
+    }
+
+    #[test]
+    fn a_zeroed_account_is_the_default_uninitialized_game() {
+        let data = vec![0; Game::LEN];
+        let game = Game::load(&data).unwrap();
+        assert!(!game.is_initialized());
+        assert_eq!(game.state, GameState::Unaccepted);
+        assert_eq!(
+            game.try_to_vec().unwrap(),
+            Game::default().try_to_vec().unwrap()
+        );
+        assert_eq!(
+            Game::from_account_data(&data).unwrap_err(),
+            ProgramError::UninitializedAccount
+        );
+    }
+
+    #[test]
+    fn turn_info_is_read_from_the_leading_bytes() {
+        let mut game = setup();
+        play_all(&mut game, &[(0, 0), (1, 1), (2, 2)]);
+        let mut data = vec![0; Game::LEN];
+        Game::pack(game, &mut data).unwrap();
+        // Ongoing is the second GameState variant
+        assert_eq!(decode_turn_info(&data), Ok((3, 1)));
+        assert_eq!(
+            decode_turn_info(&data[..Game::OFFSET_STATE]),
+            Err(ProgramError::InvalidAccountData)
+        );
+        data[Game::OFFSET_VERSION] = 0;
+        assert_eq!(
+            decode_turn_info(&data),
+            Err(Error::UnsupportedGameVersion.into())
+        );
+    }
+
+    #[test]
+    fn outcome_still_possible_counts_the_moves_each_side_has_left() {
+        let mut game = setup();
+        assert_eq!(game.outcome_still_possible(), (true, true));
+        // every line holds both symbols
+        play_all(
+            &mut game,
+            &[
+                (0, 0),
+                (0, 1),
+                (0, 2),
+                (1, 0),
+                (1, 1),
+                (2, 0),
+                (2, 1),
+                (2, 2),
+            ],
+        );
+        assert_eq!(game.outcome_still_possible(), (false, false));
+        // nothing is left to win once the game is over
+        let mut game = setup();
+        play_all(&mut game, &[(0, 0), (1, 0), (0, 1), (1, 1), (0, 2)]);
+        assert_eq!(game.outcome_still_possible(), (false, false));
+    }
+
+    #[test]
+    fn a_move_is_two_bytes_and_plays_for_the_player_to_move() {
+        let play = Move { row: 2, col: 1 };
+        assert_eq!(play.try_to_vec().unwrap(), vec![2, 1]);
+        let mut by_move = setup();
+        let mut by_player = by_move.clone();
+        by_move.apply_move(play).unwrap();
+        let player = by_player.players[0];
+        by_player.play(&player, 2, 1).unwrap();
+        assert_eq!(by_move.board, by_player.board);
+        assert_eq!(by_move.history(), vec![play]);
+    }
+
+    #[test]
+    fn payouts_cover_every_finished_state() {
+        let win = [(0, 0), (1, 0), (0, 1), (1, 1), (0, 2)];
+        let mut game = setup();
+        let one = game.players[0];
+        game.fee_bps = 250;
+        game.fee_collector = Pubkey::new_unique();
+        play_all(&mut game, &win);
+        assert_eq!(game.payouts(), vec![(game.fee_collector, 5), (one, 195)]);
+        // a yield on a dedicated escrow is split the same way, the winner takes the rounding
+        assert_eq!(
+            game.payouts_from_balance(301),
+            vec![(game.fee_collector, 7), (one, 294)]
+        );
+
+        let mut game = setup();
+        game.fee_bps = 250;
+        play_all(
+            &mut game,
+            &[
+                (0, 0),
+                (1, 1),
+                (2, 2),
+                (0, 1),
+                (2, 1),
+                (2, 0),
+                (0, 2),
+                (1, 2),
+                (1, 0),
+            ],
+        );
+        assert_eq!(game.state, GameState::Draw);
+        let [one, two] = game.players;
+        assert_eq!(game.payouts(), vec![(one, 100), (two, 100)]);
+
+        // a bonded game only gives the bonds back
+        let mut game = setup();
+        game.bond_amount = 10;
+        game.deposits = [10, 10];
+        play_all(&mut game, &win);
+        let [one, two] = game.players;
+        assert_eq!(game.payouts(), vec![(one, 10), (two, 10)]);
+    }
+
+    #[test]
+    fn the_higher_staker_moves_second_only_when_asked() {
+        let mut game = setup();
+        game.assign_first_player([200, 100]);
+        assert_eq!(game.first_player, 0);
+        game.higher_staker_moves_second = true;
+        for (stakes, first_player) in [([200, 100], 1), ([100, 200], 0), ([100, 100], 0)] {
+            game.assign_first_player(stakes);
+            assert_eq!(game.first_player, first_player);
+        }
+        game.assign_first_player([200, 100]);
+        assert_eq!(game.current_player(), game.players[1]);
+        assert_eq!(game.current_symbol(), Symbol::X);
+    }
+
+    #[test]
+    fn simulate_replays_a_move_list_and_stops_at_an_illegal_move() {
+        let players = [Pubkey::new_unique(), Pubkey::new_unique()];
+        let moves = [(0, 0), (1, 0), (0, 1), (1, 1), (0, 2)];
+        let game = simulate(players, &moves).unwrap();
+        assert_eq!(game.state, GameState::Over { winner: players[0] });
+        assert_eq!(game.history().len(), moves.len());
+        assert_eq!(
+            simulate(players, &[(0, 0), (0, 0)]).unwrap_err(),
+            Error::TileOccupied.into()
+        );
+        assert_eq!(
+            simulate(players, &[(0, 0), (1, 0), (0, 1), (1, 1), (0, 2), (2, 2)]).unwrap_err(),
+            Error::GameAlreadyOver.into()
+        );
+    }
+
+    #[test]
+    fn turns_that_disagree_with_the_state_fail_to_load() {
+        let winner = Pubkey::new_unique();
+        for (state, turns) in [
+            (GameState::Unaccepted, 1),
+            (GameState::Ongoing, 9),
+            (GameState::Over { winner }, 4),
+            (GameState::Draw, 8),
+            (GameState::Cancelled, 2),
+        ] {
+            let mut game = setup();
+            game.state = state;
+            game.turns = turns;
+            let mut data = vec![0; Game::LEN];
+            game.serialize(&mut &mut data[..]).unwrap();
+            assert_eq!(
+                Game::load(&data).unwrap_err(),
+                ProgramError::InvalidAccountData
+            );
+        }
+        // an allowed early draw explains a short game
+        let mut game = setup();
+        game.state = GameState::Draw;
+        game.turns = 8;
+        game.allow_early_draw = true;
+        game.validate_invariants().unwrap();
+    }
+
+    #[test]
+    fn the_label_is_utf8_without_its_padding() {
+        let mut game = setup();
+        assert_eq!(game.label(), Ok(""));
+        let label = "final \u{265f}".as_bytes();
+        game.label[..label.len()].copy_from_slice(label);
+        assert_eq!(game.label(), Ok("final \u{265f}"));
+        // a multi byte character cut short
+        game.label[label.len() - 1] = 0;
+        assert_eq!(game.label(), Err(Error::InvalidLabel.into()));
+    }
+
+    #[test]
+    fn the_binary_export_round_trips() {
+        let mut game = setup();
+        game.size = 4;
+        game.first_player = 1;
+        game.misere = true;
+        play_all(&mut game, &[(0, 0), (3, 3), (2, 1)]);
+        let bytes = game.to_bytes();
+        let imported = Game::from_bytes(&bytes).unwrap();
+        assert_eq!(imported.to_bytes(), bytes);
+        assert_eq!(imported.board, game.board);
+        assert_eq!(imported.current_player(), game.current_player());
+        assert_eq!((imported.size, imported.misere), (4, true));
+
+        let mut trailing = bytes.clone();
+        trailing.push(0);
+        assert_eq!(
+            Game::from_bytes(&trailing).unwrap_err(),
+            ProgramError::InvalidAccountData
+        );
+        let mut newer = bytes;
+        newer[0] += 1;
+        assert_eq!(
+            Game::from_bytes(&newer).unwrap_err(),
+            Error::UnsupportedGameVersion.into()
+        );
+    }
+
+    #[test]
+    fn len_fits_the_largest_state_exactly() {
+        let mut game = setup();
+        // every tile filled, as far as the encoding goes
+        game.board = [[Some(Symbol::X); MAX_BOARD_SIZE]; MAX_BOARD_SIZE];
+        game.state = GameState::Settled {
+            winner: Some(game.players[0]),
+        };
+        game.turns = game.tiles();
+        assert_eq!(game.try_to_vec().unwrap().len(), Game::LEN);
+        game.state = GameState::Ongoing;
+        game.turns = 0;
+        assert!(game.try_to_vec().unwrap().len() < Game::LEN);
+        assert_eq!(
+            Game::pack(game, &mut [0; Game::LEN - 1]).unwrap_err(),
+            ProgramError::InvalidAccountData
+        );
+    }
+
+    #[test]
+    fn the_move_history_keeps_every_ply_in_order() {
+        let mut game = setup();
+        game.size = 4;
+        play_all(&mut game, &[(3, 2), (0, 1), (1, 3)]);
+        assert_eq!(game.move_history[..4], [14, 1, 7, Game::NO_MOVE]);
+        assert_eq!(
+            game.history(),
+            vec![
+                Move { row: 3, col: 2 },
+                Move { row: 0, col: 1 },
+                Move { row: 1, col: 3 }
+            ]
+        );
+        game.reset_board();
+        assert!(game.history().is_empty());
+    }
+
+    #[test]
+    fn uneven_stakes_pay_the_whole_pot_or_refund_each_side() {
+        let mut game = setup();
+        game.stake_amount_two = 40;
+        game.deposits = [100, 40];
+        assert_eq!(game.stakes(), [100, 40]);
+        assert_eq!(game.escrowed_amount(1), 40);
+        let mut won = game.clone();
+        play_all(&mut won, &[(0, 0), (1, 0), (0, 1), (1, 1), (0, 2)]);
+        assert_eq!(won.payouts(), vec![(game.players[0], 140)]);
+        game.state = GameState::Draw;
+        game.allow_early_draw = true;
+        assert_eq!(
+            game.payouts(),
+            vec![(game.players[0], 100), (game.players[1], 40)]
+        );
+    }
+
+    #[test]
+    fn a_series_resets_the_board_until_a_player_takes_the_match() {
+        let win = [(0, 0), (1, 0), (0, 1), (1, 1), (0, 2)];
+        let mut game = setup();
+        game.series_target = 2;
+        let [one, two] = game.players;
+        play_all(&mut game, &win);
+        assert_eq!(game.next_round(), Some(GameState::Over { winner: one }));
+        assert_eq!((game.wins, game.turns), ([1, 0], 0));
+        assert_eq!(game.state, GameState::Ongoing);
+        // the openers alternate, so the same moves win the next game for player two
+        assert_eq!(game.current_player(), two);
+        play_all(&mut game, &win);
+        assert_eq!(game.next_round(), Some(GameState::Over { winner: two }));
+        // a full board is replayed without a score
+        play_all(
+            &mut game,
+            &[
+                (0, 0),
+                (1, 1),
+                (2, 2),
+                (0, 1),
+                (2, 1),
+                (2, 0),
+                (0, 2),
+                (1, 2),
+                (1, 0),
+            ],
+        );
+        assert_eq!(game.next_round(), Some(GameState::Draw));
+        assert_eq!(game.wins, [1, 1]);
+        play_all(&mut game, &win);
+        assert_eq!(game.next_round(), None);
+        assert_eq!(game.wins, [1, 2]);
+        assert_eq!(game.state, GameState::Over { winner: two });
+    }
+
+    #[test]
+    fn client_decoding_needs_a_whole_account() {
+        let game = setup();
+        let mut data = vec![0; Game::LEN];
+        Game::pack(game.clone(), &mut data).unwrap();
+        assert_eq!(
+            Game::from_account_data(&data).unwrap().players,
+            game.players
+        );
+        for len in [Game::LEN - 1, Game::LEN + 1] {
+            data.resize(len, 0);
+            assert_eq!(
+                Game::from_account_data(&data).unwrap_err(),
+                ProgramError::InvalidAccountData
+            );
+        }
+    }
+
+    #[test]
+    fn play_rejects_each_state_but_ongoing_with_its_own_error() {
+        let winner = Pubkey::new_unique();
+        for (state, error) in [
+            (GameState::Unaccepted, Error::UnacceptedGame),
+            (GameState::Over { winner }, Error::GameAlreadyOver),
+            (GameState::Draw, Error::GameAlreadyOver),
+            (GameState::Settled { winner: None }, Error::AlreadySettled),
+            (GameState::Cancelled, Error::GameCancelled),
+        ] {
+            let mut game = setup();
+            let player = game.players[0];
+            game.state = state;
+            assert_eq!(game.play(&player, 0, 0), Err(error.into()));
+            assert_eq!(game.board[0][0], None);
+        }
     }
 }
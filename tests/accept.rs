@@ -0,0 +1,112 @@
+mod common;
+
+use borsh::BorshSerialize;
+use common::{accept_game, create_game, instruction_error, setup, setup_with, BALANCE, STAKE};
+use solana_program::{
+    account_info::AccountInfo,
+    entrypoint::ProgramResult,
+    instruction::{AccountMeta, InstructionError},
+    program::invoke,
+    pubkey::Pubkey,
+};
+use solana_program_test::{processor, BanksClientError};
+use solana_sdk::{
+    signature::{Keypair, Signer},
+    transaction::TransactionError,
+};
+use tic_tac_toe::error::Error;
+use tic_tac_toe::instruction::Instruction;
+use tic_tac_toe::state::{GameState, Move};
+
+#[tokio::test]
+async fn a_failed_game_write_reverts_the_accept_and_its_deposit() {
+    let mut env = setup().await;
+    let game = Keypair::new();
+    let game_key = game.pubkey();
+    let create = env.instruction(&create_game(env.player(1)), &env.keys(game_key, 0));
+    let player = env.players[0].insecure_clone();
+    env.send(&[create], &[&player, &game]).await.unwrap();
+
+    // accepting never grows the game, so the write that no longer fits is the first move's,
+    // in the same transaction as the accept
+    let mut account = env.account(game_key).await.unwrap();
+    let data = env.game(game_key).await.try_to_vec().unwrap();
+    assert!(data.len() < account.data.len());
+    account.data = data;
+    env.context.set_account(&game_key, &account.into());
+
+    let instructions = [
+        env.instruction(&accept_game(), &env.keys(game_key, 1)),
+        env.instruction(
+            &Instruction::PlayGame(Move { row: 0, col: 0 }),
+            &env.keys(game_key, 0),
+        ),
+    ];
+    let players = [
+        env.players[0].insecure_clone(),
+        env.players[1].insecure_clone(),
+    ];
+    let result = env.send(&instructions, &[&players[0], &players[1]]).await;
+    assert!(matches!(
+        result,
+        Err(BanksClientError::TransactionError(
+            TransactionError::InstructionError(1, InstructionError::AccountDataTooSmall)
+        ))
+    ));
+
+    // nothing of the accept is left, the escrow only holds the creator's stake
+    assert_eq!(env.game(game_key).await.state, GameState::Unaccepted);
+    assert_eq!(env.token_balance(env.token_accounts[1]).await, BALANCE);
+    assert_eq!(env.token_balance(env.escrow()).await, STAKE);
+}
+
+// accepts the game twice, once as a normal caller and again as if it had been re-entered,
+// the runtime itself refuses a token program calling back into its caller
+fn reentrant_accept(_: &Pubkey, accounts: &[AccountInfo], data: &[u8]) -> ProgramResult {
+    let (program, accounts) = accounts.split_last().unwrap();
+    let accept = solana_program::instruction::Instruction {
+        program_id: *program.key,
+        accounts: accounts
+            .iter()
+            .map(|account| AccountMeta {
+                pubkey: *account.key,
+                is_signer: account.is_signer,
+                is_writable: account.is_writable,
+            })
+            .collect(),
+        data: data.to_vec(),
+    };
+    invoke(&accept, accounts)?;
+    invoke(&accept, accounts)
+}
+
+#[tokio::test]
+async fn a_reentrant_caller_can_not_accept_twice() {
+    let caller = Pubkey::new_unique();
+    let mut env = setup_with(|program_test| {
+        program_test.add_program("reentrant_accept", caller, processor!(reentrant_accept))
+    })
+    .await;
+    let game = Keypair::new();
+    let game_key = game.pubkey();
+    let create = env.instruction(&create_game(env.player(1)), &env.keys(game_key, 0));
+    let player = env.players[0].insecure_clone();
+    env.send(&[create], &[&player, &game]).await.unwrap();
+
+    let mut accept = env.instruction(&accept_game(), &env.keys(game_key, 1));
+    accept.program_id = caller;
+    accept
+        .accounts
+        .push(AccountMeta::new_readonly(env.program_id, false));
+    let player = env.players[1].insecure_clone();
+    let result = env.send(&[accept], &[&player]).await;
+    assert_eq!(
+        instruction_error(result),
+        InstructionError::Custom(Error::AlreadyAccepted as u32)
+    );
+
+    // the failed second accept takes the first one down with it
+    assert_eq!(env.game(game_key).await.state, GameState::Unaccepted);
+    assert_eq!(env.token_balance(env.token_accounts[1]).await, BALANCE);
+    assert_eq!(env.token_balance(env.escrow()).await, STAKE);
+}
@@ -0,0 +1,127 @@
+mod common;
+
+use common::{create_game, setup, Env, BALANCE, STAKE};
+use solana_sdk::{
+    instruction::AccountMeta,
+    pubkey::Pubkey,
+    signature::{Keypair, Signer},
+};
+use tic_tac_toe::instruction::Instruction;
+use tic_tac_toe::processor::find_escrow_address;
+use tic_tac_toe::state::{GameState, Stake};
+
+const EXTRA_STAKE: u64 = 50;
+
+// a started game staking STAKE of env.mint and EXTRA_STAKE of a second mint per player,
+// returns the second mint and the players' token accounts of it
+async fn start_basket_game(env: &mut Env, game: &Keypair) -> (Pubkey, [Pubkey; 2]) {
+    let mint = env.create_mint().await;
+    let mut token_accounts = [Pubkey::default(); 2];
+    for (i, token_account) in token_accounts.iter_mut().enumerate() {
+        *token_account = env.create_token_account(i, mint).await;
+        env.mint_to(*token_account, BALANCE).await;
+    }
+    let escrow = find_escrow_address(&env.program_id, &mint, None).0;
+
+    let mut create = create_game(env.player(1));
+    if let Instruction::CreateGame { extra_stakes, .. } = &mut create {
+        extra_stakes.push(Stake {
+            mint,
+            amount: EXTRA_STAKE,
+        });
+    }
+    let mut create = env.instruction(&create, &env.keys(game.pubkey(), 0));
+    create.accounts.extend([
+        AccountMeta::new_readonly(mint, false),
+        AccountMeta::new(escrow, false),
+        AccountMeta::new(token_accounts[0], false),
+    ]);
+    let player = env.players[0].insecure_clone();
+    env.send(&[create], &[&player, game]).await.unwrap();
+
+    let mut accept = env.instruction(&common::accept_game(), &env.keys(game.pubkey(), 1));
+    accept.accounts.extend([
+        AccountMeta::new(escrow, false),
+        AccountMeta::new(token_accounts[1], false),
+    ]);
+    let player = env.players[1].insecure_clone();
+    env.send(&[accept], &[&player]).await.unwrap();
+    assert_eq!(env.token_balance(escrow).await, 2 * EXTRA_STAKE);
+    (mint, token_accounts)
+}
+
+fn close() -> Instruction {
+    Instruction::CloseGame {
+        keep_trophy: false,
+        create_ata_if_missing: false,
+        rent_recipient: None,
+    }
+}
+
+#[tokio::test]
+async fn the_winner_takes_every_mint_of_the_basket() {
+    let mut env = setup().await;
+    let game = Keypair::new();
+    let game_key = game.pubkey();
+    let (mint, token_accounts) = start_basket_game(&mut env, &game).await;
+    env.play_all(game_key, &[(0, 0), (1, 0), (0, 1), (1, 1), (0, 2)])
+        .await;
+    assert_eq!(
+        env.game(game_key).await.state,
+        GameState::Over {
+            winner: env.player(0)
+        }
+    );
+
+    let mut close = env.instruction(&close(), &env.keys(game_key, 0));
+    close.accounts.extend([
+        AccountMeta::new(find_escrow_address(&env.program_id, &mint, None).0, false),
+        AccountMeta::new(token_accounts[0], false),
+    ]);
+    let player = env.players[0].insecure_clone();
+    env.send(&[close], &[&player]).await.unwrap();
+
+    for (token_accounts, stake) in [(env.token_accounts, STAKE), (token_accounts, EXTRA_STAKE)] {
+        assert_eq!(env.token_balance(token_accounts[0]).await, BALANCE + stake);
+        assert_eq!(env.token_balance(token_accounts[1]).await, BALANCE - stake);
+    }
+}
+
+#[tokio::test]
+async fn a_draw_refunds_every_mint_of_the_basket() {
+    let mut env = setup().await;
+    let game = Keypair::new();
+    let game_key = game.pubkey();
+    let (mint, token_accounts) = start_basket_game(&mut env, &game).await;
+    env.play_all(
+        game_key,
+        &[
+            (0, 0),
+            (0, 1),
+            (0, 2),
+            (1, 1),
+            (1, 0),
+            (2, 0),
+            (1, 2),
+            (2, 2),
+            (2, 1),
+        ],
+    )
+    .await;
+    assert_eq!(env.game(game_key).await.state, GameState::Draw);
+
+    let mut keys = env.keys(game_key, 0);
+    keys.token_account_two = env.token_accounts[1];
+    let mut close = env.instruction(&close(), &keys);
+    close.accounts.extend([
+        AccountMeta::new(find_escrow_address(&env.program_id, &mint, None).0, false),
+        AccountMeta::new(token_accounts[0], false),
+        AccountMeta::new(token_accounts[1], false),
+    ]);
+    let player = env.players[0].insecure_clone();
+    env.send(&[close], &[&player]).await.unwrap();
+
+    for token_account in env.token_accounts.into_iter().chain(token_accounts) {
+        assert_eq!(env.token_balance(token_account).await, BALANCE);
+    }
+}
@@ -0,0 +1,326 @@
+// shared by the integration tests, not every test file uses every helper
+#![allow(dead_code)]
+
+use solana_program::program_pack::Pack;
+use solana_program_test::{processor, BanksClientError, ProgramTest, ProgramTestContext};
+use solana_sdk::{
+    account::Account,
+    instruction::InstructionError,
+    pubkey::Pubkey,
+    signature::{Keypair, Signer},
+    system_instruction,
+    transaction::{Transaction, TransactionError},
+};
+use spl_associated_token_account::{
+    get_associated_token_address, instruction::create_associated_token_account,
+};
+use tic_tac_toe::instruction::{Instruction, InstructionKeys};
+use tic_tac_toe::processor::process_instruction;
+use tic_tac_toe::state::{Game, GlobalStats, Move};
+
+// what each player's token account starts with
+pub const BALANCE: u64 = 1_000;
+pub const STAKE: u64 = 100;
+// lamports each player's wallet starts with, the test payer pays the fees
+pub const LAMPORTS: u64 = 10_000_000_000;
+
+pub struct Env {
+    pub context: ProgramTestContext,
+    pub program_id: Pubkey,
+    pub mint: Pubkey,
+    pub players: [Keypair; 2],
+    // the players' associated token accounts of mint, holding BALANCE each
+    pub token_accounts: [Pubkey; 2],
+}
+
+// the program runs natively, the token programs are the real ones program-test ships with
+pub fn program_test(program_id: Pubkey, players: &[Keypair; 2]) -> ProgramTest {
+    let mut program_test =
+        ProgramTest::new("tic_tac_toe", program_id, processor!(process_instruction));
+    for player in players {
+        program_test.add_account(
+            player.pubkey(),
+            Account::new(LAMPORTS, 0, &solana_program::system_program::ID),
+        );
+    }
+    program_test
+}
+
+// a started bank with a legacy token mint and two funded players
+pub async fn setup() -> Env {
+    setup_with(|_| {}).await
+}
+
+// same as setup, with the chance to add programs or accounts before the bank starts
+pub async fn setup_with(configure: impl FnOnce(&mut ProgramTest)) -> Env {
+    let program_id = Pubkey::new_unique();
+    let players = [Keypair::new(), Keypair::new()];
+    let mut program_test = program_test(program_id, &players);
+    configure(&mut program_test);
+    let context = program_test.start_with_context().await;
+
+    let mut env = Env {
+        context,
+        program_id,
+        mint: Pubkey::default(),
+        token_accounts: [Pubkey::default(); 2],
+        players,
+    };
+    env.mint = env.create_mint().await;
+    for i in 0..2 {
+        env.token_accounts[i] = env.create_token_account(i, env.mint).await;
+        env.mint_to(env.token_accounts[i], BALANCE).await;
+    }
+    env
+}
+
+// a plain game between the two players staking STAKE each
+pub fn create_game(player_two: Pubkey) -> Instruction {
+    Instruction::CreateGame {
+        player_two,
+        stake_amount: STAKE,
+        stake_amount_two: STAKE,
+        bond_amount: 0,
+        fee_bps: 0,
+        fee_collector: Pubkey::default(),
+        expiry_slots: 0,
+        max_duration_slots: 0,
+        move_timeout_slots: 0,
+        allow_early_draw: false,
+        higher_staker_moves_second: false,
+        misere: false,
+        board_size: 3,
+        win_len: 3,
+        series_target: 0,
+        dedicated_escrow: false,
+        game_nonce: None,
+        label: [0; 32],
+        extra_stakes: Vec::new(),
+        join_secret_hash: None,
+    }
+}
+
+// the error a failed single instruction transaction stopped at
+pub fn instruction_error(result: Result<(), BanksClientError>) -> InstructionError {
+    match result.unwrap_err().unwrap() {
+        TransactionError::InstructionError(0, error) => error,
+        error => panic!("unexpected transaction error: {error}"),
+    }
+}
+
+pub fn accept_game() -> Instruction {
+    Instruction::AcceptGame {
+        fee_bps: 0,
+        fee_collector: Pubkey::default(),
+        join_secret: None,
+    }
+}
+
+impl Env {
+    pub fn player(&self, i: usize) -> Pubkey {
+        self.players[i].pubkey()
+    }
+
+    // keys for an instruction signed by player i, paying out to their token account
+    pub fn keys(&self, game: Pubkey, i: usize) -> InstructionKeys {
+        InstructionKeys {
+            program_id: self.program_id,
+            player_one: self.player(0),
+            player_two: self.player(1),
+            player: self.player(i),
+            game,
+            mint: self.mint,
+            token_account: self.token_accounts[i],
+            token_account_two: Pubkey::default(),
+            dedicated_escrow: false,
+            token_program: spl_token::ID,
+        }
+    }
+
+    pub fn instruction(
+        &self,
+        instruction: &Instruction,
+        keys: &InstructionKeys,
+    ) -> solana_sdk::instruction::Instruction {
+        solana_sdk::instruction::Instruction {
+            program_id: self.program_id,
+            accounts: instruction.account_metas(keys),
+            data: instruction.pack(),
+        }
+    }
+
+    // a transaction of the instructions with the test payer paying the fees
+    async fn transaction(
+        &mut self,
+        instructions: &[solana_sdk::instruction::Instruction],
+        signers: &[&Keypair],
+    ) -> Transaction {
+        let mut all_signers = vec![&self.context.payer];
+        all_signers.extend_from_slice(signers);
+        let blockhash = self
+            .context
+            .banks_client
+            .get_latest_blockhash()
+            .await
+            .unwrap();
+        Transaction::new_signed_with_payer(
+            instructions,
+            Some(&self.context.payer.pubkey()),
+            &all_signers,
+            blockhash,
+        )
+    }
+
+    pub async fn send(
+        &mut self,
+        instructions: &[solana_sdk::instruction::Instruction],
+        signers: &[&Keypair],
+    ) -> Result<(), BanksClientError> {
+        let transaction = self.transaction(instructions, signers).await;
+        self.context
+            .banks_client
+            .process_transaction(transaction)
+            .await
+    }
+
+    // like send, returning the program logs of the transaction
+    pub async fn send_with_logs(
+        &mut self,
+        instructions: &[solana_sdk::instruction::Instruction],
+        signers: &[&Keypair],
+    ) -> Result<Vec<String>, BanksClientError> {
+        let transaction = self.transaction(instructions, signers).await;
+        let result = self
+            .context
+            .banks_client
+            .process_transaction_with_metadata(transaction)
+            .await?;
+        result.result.map_err(BanksClientError::TransactionError)?;
+        Ok(result.metadata.unwrap().log_messages)
+    }
+
+    // sends one game instruction signed by player i
+    pub async fn run(
+        &mut self,
+        instruction: &Instruction,
+        keys: &InstructionKeys,
+        i: usize,
+    ) -> Result<(), BanksClientError> {
+        let instruction = self.instruction(instruction, keys);
+        let player = self.players[i].insecure_clone();
+        self.send(&[instruction], &[&player]).await
+    }
+
+    // creates game with player one's stake and has player two accept it
+    pub async fn start_game(&mut self, game: &Keypair, create: Instruction) {
+        let keys = self.keys(game.pubkey(), 0);
+        let instruction = self.instruction(&create, &keys);
+        let player = self.players[0].insecure_clone();
+        self.send(&[instruction], &[&player, game]).await.unwrap();
+        let keys = self.keys(game.pubkey(), 1);
+        self.run(&accept_game(), &keys, 1).await.unwrap();
+    }
+
+    pub async fn play(
+        &mut self,
+        game: Pubkey,
+        i: usize,
+        (row, col): (u8, u8),
+    ) -> Result<(), BanksClientError> {
+        let keys = self.keys(game, i);
+        self.run(&Instruction::PlayGame(Move { row, col }), &keys, i)
+            .await
+    }
+
+    // plays the moves alternately, starting with player one
+    pub async fn play_all(&mut self, game: Pubkey, moves: &[(u8, u8)]) {
+        for (turn, &play) in moves.iter().enumerate() {
+            self.play(game, turn % 2, play).await.unwrap();
+        }
+    }
+
+    pub async fn account(&mut self, key: Pubkey) -> Option<Account> {
+        self.context.banks_client.get_account(key).await.unwrap()
+    }
+
+    pub async fn game(&mut self, key: Pubkey) -> Game {
+        Game::load(&self.account(key).await.unwrap().data).unwrap()
+    }
+
+    pub async fn lamports(&mut self, key: Pubkey) -> u64 {
+        self.account(key)
+            .await
+            .map_or(0, |account| account.lamports)
+    }
+
+    pub async fn token_balance(&mut self, key: Pubkey) -> u64 {
+        let account = self.account(key).await.unwrap();
+        spl_token::state::Account::unpack(&account.data)
+            .unwrap()
+            .amount
+    }
+
+    pub async fn stats(&mut self) -> GlobalStats {
+        let (stats, _) = Pubkey::find_program_address(&["stats".as_bytes()], &self.program_id);
+        GlobalStats::load(&self.account(stats).await.unwrap().data).unwrap()
+    }
+
+    pub fn escrow(&self) -> Pubkey {
+        tic_tac_toe::processor::find_escrow_address(&self.program_id, &self.mint, None).0
+    }
+
+    pub async fn create_mint(&mut self) -> Pubkey {
+        let mint = Keypair::new();
+        let payer = self.context.payer.pubkey();
+        let rent = self.context.banks_client.get_rent().await.unwrap();
+        let instructions = [
+            system_instruction::create_account(
+                &payer,
+                &mint.pubkey(),
+                rent.minimum_balance(spl_token::state::Mint::LEN),
+                spl_token::state::Mint::LEN as u64,
+                &spl_token::ID,
+            ),
+            spl_token::instruction::initialize_mint2(
+                &spl_token::ID,
+                &mint.pubkey(),
+                &payer,
+                None,
+                0,
+            )
+            .unwrap(),
+        ];
+        self.send(&instructions, &[&mint]).await.unwrap();
+        mint.pubkey()
+    }
+
+    // player i's associated token account of mint
+    pub async fn create_token_account(&mut self, i: usize, mint: Pubkey) -> Pubkey {
+        let instruction = create_associated_token_account(
+            &self.context.payer.pubkey(),
+            &self.player(i),
+            &mint,
+            &spl_token::ID,
+        );
+        self.send(&[instruction], &[]).await.unwrap();
+        get_associated_token_address(&self.player(i), &mint)
+    }
+
+    // the payer is the authority of every mint the harness creates
+    pub async fn mint_to(&mut self, token_account: Pubkey, amount: u64) {
+        let mint =
+            spl_token::state::Account::unpack(&self.account(token_account).await.unwrap().data)
+                .unwrap()
+                .mint;
+        let instruction = spl_token::instruction::mint_to(
+            &spl_token::ID,
+            &mint,
+            &token_account,
+            &self.context.payer.pubkey(),
+            &[],
+            amount,
+        )
+        .unwrap();
+        self.send(&[instruction], &[]).await.unwrap();
+    }
+}
@@ -0,0 +1,58 @@
+mod common;
+
+use common::{create_game, setup};
+use solana_sdk::signature::{Keypair, Signer};
+use tic_tac_toe::event::LOG_PREFIX;
+use tic_tac_toe::instruction::Instruction;
+use tic_tac_toe::state::Move;
+
+// the event lines among the program logs of a transaction
+fn events(logs: Vec<String>) -> Vec<String> {
+    logs.into_iter()
+        .filter_map(|log| log.strip_prefix("Program log: ").map(str::to_string))
+        .filter(|log| log.starts_with(LOG_PREFIX))
+        .collect()
+}
+
+#[tokio::test]
+async fn a_move_logs_its_event_with_the_coordinates() {
+    let mut env = setup().await;
+    let game = Keypair::new();
+    let game_key = game.pubkey();
+    env.start_game(&game, create_game(env.player(1))).await;
+    let id = env.game(game_key).await.id();
+
+    let play = env.instruction(
+        &Instruction::PlayGame(Move { row: 1, col: 2 }),
+        &env.keys(game_key, 0),
+    );
+    let player = env.players[0].insecure_clone();
+    let logs = env.send_with_logs(&[play], &[&player]).await.unwrap();
+    assert_eq!(
+        events(logs),
+        [format!(
+            "TTT-EVENT: kind=moved game={id} player={} row=1 col=2 symbol=X turns=1",
+            env.player(0)
+        )]
+    );
+
+    // the winning move is followed by the win
+    for (i, play) in [(1, (0, 0)), (0, (0, 2)), (1, (1, 1))] {
+        env.play(game_key, i, play).await.unwrap();
+    }
+    let play = env.instruction(
+        &Instruction::PlayGame(Move { row: 2, col: 2 }),
+        &env.keys(game_key, 0),
+    );
+    let logs = env.send_with_logs(&[play], &[&player]).await.unwrap();
+    assert_eq!(
+        events(logs),
+        [
+            format!(
+                "TTT-EVENT: kind=moved game={id} player={} row=2 col=2 symbol=X turns=5",
+                env.player(0)
+            ),
+            format!("TTT-EVENT: kind=won game={id} winner={}", env.player(0)),
+        ]
+    );
+}
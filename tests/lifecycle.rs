@@ -0,0 +1,193 @@
+mod common;
+
+use common::{accept_game, create_game, setup, Env, BALANCE, LAMPORTS, STAKE};
+use solana_sdk::{
+    pubkey::Pubkey,
+    signature::{Keypair, Signer},
+    system_program,
+};
+use tic_tac_toe::instruction::{Instruction, InstructionKeys};
+use tic_tac_toe::processor::find_native_escrow_address;
+use tic_tac_toe::state::{GameState, GlobalStats};
+
+#[tokio::test]
+async fn a_won_game_pays_the_pot_to_the_winner_and_returns_the_rent() {
+    let mut env = setup().await;
+    let game = Keypair::new();
+    let game_key = game.pubkey();
+    let winner = env.player(0);
+    env.start_game(&game, create_game(env.player(1))).await;
+    assert_eq!(env.game(game_key).await.state, GameState::Ongoing);
+    assert_eq!(env.token_balance(env.escrow()).await, 2 * STAKE);
+
+    env.play_all(game_key, &[(0, 0), (1, 0), (0, 1), (1, 1), (0, 2)])
+        .await;
+    let state = env.game(game_key).await;
+    assert_eq!(state.state, GameState::Over { winner });
+    assert_eq!(state.board_rows(), ["XXX", "OO-", "---"]);
+
+    let game_rent = env.lamports(game_key).await;
+    let lamports_before = env.lamports(winner).await;
+    let keys = env.keys(game_key, 0);
+    let close = Instruction::CloseGame {
+        keep_trophy: false,
+        create_ata_if_missing: false,
+        rent_recipient: None,
+    };
+    env.run(&close, &keys, 0).await.unwrap();
+
+    assert_eq!(
+        env.token_balance(env.token_accounts[0]).await,
+        BALANCE + STAKE
+    );
+    assert_eq!(
+        env.token_balance(env.token_accounts[1]).await,
+        BALANCE - STAKE
+    );
+    assert_eq!(env.token_balance(env.escrow()).await, 0);
+    assert!(env.account(game_key).await.is_none());
+    assert_eq!(env.lamports(winner).await, lamports_before + game_rent);
+}
+
+#[tokio::test]
+async fn a_casual_game_is_played_and_closed_without_token_accounts() {
+    let mut env = setup().await;
+    let game = Keypair::new();
+    let game_key = game.pubkey();
+    let (escrow, _) = find_native_escrow_address(&env.program_id, &game_key);
+    let winner = env.player(0);
+    // nothing staked, so the lamport layout with wallets in place of the token accounts
+    let keys = |env: &Env, i: usize| InstructionKeys {
+        mint: system_program::ID,
+        token_account: env.player(i),
+        ..env.keys(game_key, i)
+    };
+
+    let mut create = create_game(env.player(1));
+    if let Instruction::CreateGame {
+        stake_amount,
+        stake_amount_two,
+        ..
+    } = &mut create
+    {
+        (*stake_amount, *stake_amount_two) = (0, 0);
+    }
+    let create = env.instruction(&create, &keys(&env, 0));
+    let player = env.players[0].insecure_clone();
+    env.send(&[create], &[&player, &game]).await.unwrap();
+    env.run(&accept_game(), &keys(&env, 1), 1).await.unwrap();
+    assert_eq!(env.game(game_key).await.state, GameState::Ongoing);
+    assert!(env.account(escrow).await.is_none());
+
+    env.play_all(game_key, &[(0, 0), (1, 0), (0, 1), (1, 1), (0, 2)])
+        .await;
+    assert_eq!(env.game(game_key).await.state, GameState::Over { winner });
+
+    let game_rent = env.lamports(game_key).await;
+    let lamports_before = env.lamports(winner).await;
+    let close = Instruction::CloseGame {
+        keep_trophy: false,
+        create_ata_if_missing: false,
+        rent_recipient: None,
+    };
+    let close_keys = InstructionKeys {
+        token_account: Pubkey::default(),
+        ..keys(&env, 0)
+    };
+    env.run(&close, &close_keys, 0).await.unwrap();
+
+    assert!(env.account(game_key).await.is_none());
+    assert!(env.account(escrow).await.is_none());
+    assert_eq!(env.lamports(winner).await, lamports_before + game_rent);
+    for i in 0..2 {
+        assert_eq!(env.token_balance(env.token_accounts[i]).await, BALANCE);
+    }
+}
+
+#[tokio::test]
+async fn a_lamport_game_escrows_both_stakes_and_pays_them_with_the_escrow_rent() {
+    const LAMPORT_STAKE: u64 = 1_000_000;
+    let mut env = setup().await;
+    let game = Keypair::new();
+    let game_key = game.pubkey();
+    let (escrow, _) = find_native_escrow_address(&env.program_id, &game_key);
+    let [winner, loser] = [env.player(0), env.player(1)];
+    // the system program as mint, the wallets stand in for the token accounts
+    let keys = |env: &Env, i: usize| InstructionKeys {
+        mint: system_program::ID,
+        token_account: env.player(i),
+        ..env.keys(game_key, i)
+    };
+    let rent = env.context.banks_client.get_rent().await.unwrap();
+
+    let mut create = create_game(loser);
+    if let Instruction::CreateGame {
+        stake_amount,
+        stake_amount_two,
+        ..
+    } = &mut create
+    {
+        (*stake_amount, *stake_amount_two) = (LAMPORT_STAKE, LAMPORT_STAKE);
+    }
+    let create = env.instruction(&create, &keys(&env, 0));
+    let player = env.players[0].insecure_clone();
+    env.send(&[create], &[&player, &game]).await.unwrap();
+    let escrow_rent = rent.minimum_balance(0);
+    assert_eq!(env.lamports(escrow).await, escrow_rent + LAMPORT_STAKE);
+
+    env.run(&accept_game(), &keys(&env, 1), 1).await.unwrap();
+    assert_eq!(env.lamports(escrow).await, escrow_rent + 2 * LAMPORT_STAKE);
+    assert_eq!(env.lamports(loser).await, LAMPORTS - LAMPORT_STAKE);
+
+    env.play_all(game_key, &[(0, 0), (1, 0), (0, 1), (1, 1), (0, 2)])
+        .await;
+    let close = Instruction::CloseGame {
+        keep_trophy: false,
+        create_ata_if_missing: false,
+        rent_recipient: None,
+    };
+    env.run(&close, &keys(&env, 0), 0).await.unwrap();
+
+    // the winner gets the pot and both rents back, the stats account's rent stays paid
+    let stats_rent = rent.minimum_balance(GlobalStats::LEN);
+    assert!(env.account(escrow).await.is_none());
+    assert!(env.account(game_key).await.is_none());
+    assert_eq!(
+        env.lamports(winner).await,
+        LAMPORTS - stats_rent + LAMPORT_STAKE
+    );
+    assert_eq!(env.lamports(loser).await, LAMPORTS - LAMPORT_STAKE);
+}
+
+#[tokio::test]
+async fn the_stats_follow_a_game_from_create_to_close() {
+    let mut env = setup().await;
+    let game = Keypair::new();
+    let game_key = game.pubkey();
+    let create = env.instruction(&create_game(env.player(1)), &env.keys(game_key, 0));
+    let player = env.players[0].insecure_clone();
+    env.send(&[create], &[&player, &game]).await.unwrap();
+    let stats = env.stats().await;
+    assert_eq!((stats.games_created, stats.games_settled), (1, 0));
+    assert_eq!(stats.total_staked, STAKE);
+
+    // only create_game counts, accepting leaves the counters alone
+    env.run(&accept_game(), &env.keys(game_key, 1), 1)
+        .await
+        .unwrap();
+    let stats = env.stats().await;
+    assert_eq!((stats.games_created, stats.games_settled), (1, 0));
+    assert_eq!(stats.total_staked, STAKE);
+
+    env.play_all(game_key, &[(0, 0), (1, 0), (0, 1), (1, 1), (0, 2)])
+        .await;
+    let close = Instruction::CloseGame {
+        keep_trophy: false,
+        create_ata_if_missing: false,
+        rent_recipient: None,
+    };
+    env.run(&close, &env.keys(game_key, 0), 0).await.unwrap();
+    let stats = env.stats().await;
+    assert_eq!((stats.games_created, stats.games_settled), (1, 1));
+    assert_eq!(stats.total_staked, STAKE);
+}
@@ -0,0 +1,37 @@
+mod common;
+
+use common::{create_game, setup};
+use solana_sdk::signature::{Keypair, Signer};
+use tic_tac_toe::instruction::Instruction;
+use tic_tac_toe::state::{GameState, Move, Symbol};
+
+#[tokio::test]
+async fn both_moves_of_a_transaction_are_stored() {
+    let mut env = setup().await;
+    let game = Keypair::new();
+    let game_key = game.pubkey();
+    env.start_game(&game, create_game(env.player(1))).await;
+
+    // the second move is only legal if the first one was written back
+    let moves = [Move { row: 1, col: 1 }, Move { row: 0, col: 2 }];
+    let instructions: Vec<_> = moves
+        .iter()
+        .enumerate()
+        .map(|(i, &play)| env.instruction(&Instruction::PlayGame(play), &env.keys(game_key, i)))
+        .collect();
+    let players = [
+        env.players[0].insecure_clone(),
+        env.players[1].insecure_clone(),
+    ];
+    env.send(&instructions, &[&players[0], &players[1]])
+        .await
+        .unwrap();
+
+    let played = env.game(game_key).await;
+    assert_eq!(played.state, GameState::Ongoing);
+    assert_eq!(played.turns, 2);
+    assert_eq!(played.board_cell(1, 1), Some(Symbol::X));
+    assert_eq!(played.board_cell(0, 2), Some(Symbol::O));
+    assert_eq!(played.board_rows(), ["--O", "-X-", "---"]);
+    assert_eq!(played.history(), moves);
+}
@@ -0,0 +1,73 @@
+mod common;
+
+use common::{create_game, instruction_error, setup, BALANCE, STAKE};
+use solana_sdk::{
+    clock::Clock,
+    instruction::InstructionError,
+    signature::{Keypair, Signer},
+};
+use tic_tac_toe::error::Error;
+use tic_tac_toe::instruction::Instruction;
+use tic_tac_toe::state::GameState;
+
+const MOVE_TIMEOUT_SLOTS: u64 = 100;
+
+#[tokio::test]
+async fn a_stalled_game_is_claimed_only_after_the_timeout() {
+    let mut env = setup().await;
+    let game = Keypair::new();
+    let game_key = game.pubkey();
+    let mut create = create_game(env.player(1));
+    if let Instruction::CreateGame {
+        move_timeout_slots, ..
+    } = &mut create
+    {
+        *move_timeout_slots = MOVE_TIMEOUT_SLOTS;
+    }
+    env.start_game(&game, create).await;
+    env.play(game_key, 0, (1, 1)).await.unwrap();
+    let last_move_slot = env.game(game_key).await.last_move_slot;
+
+    // player two still has time to move
+    let keys = env.keys(game_key, 0);
+    env.context
+        .warp_to_slot(last_move_slot + MOVE_TIMEOUT_SLOTS)
+        .unwrap();
+    assert_eq!(
+        instruction_error(env.run(&Instruction::ClaimTimeout, &keys, 0).await),
+        InstructionError::Custom(Error::TimeoutNotReached as u32)
+    );
+    assert_eq!(env.game(game_key).await.state, GameState::Ongoing);
+
+    env.context
+        .warp_to_slot(last_move_slot + MOVE_TIMEOUT_SLOTS + 1)
+        .unwrap();
+    env.run(&Instruction::ClaimTimeout, &keys, 0).await.unwrap();
+    let claimed = env.game(game_key).await;
+    let now = env
+        .context
+        .banks_client
+        .get_sysvar::<Clock>()
+        .await
+        .unwrap()
+        .slot;
+    assert_eq!(
+        claimed.state,
+        GameState::Over {
+            winner: env.player(0)
+        }
+    );
+    assert_eq!(claimed.last_move_slot, now);
+
+    // the claim is paid out like any other win
+    let close = Instruction::CloseGame {
+        keep_trophy: false,
+        create_ata_if_missing: false,
+        rent_recipient: None,
+    };
+    env.run(&close, &keys, 0).await.unwrap();
+    assert_eq!(
+        env.token_balance(env.token_accounts[0]).await,
+        BALANCE + STAKE
+    );
+}
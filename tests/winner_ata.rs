@@ -0,0 +1,67 @@
+mod common;
+
+use common::{create_game, setup, STAKE};
+use solana_sdk::{
+    instruction::AccountMeta,
+    signature::{Keypair, Signer},
+};
+use spl_associated_token_account::get_associated_token_address;
+use tic_tac_toe::instruction::Instruction;
+
+#[tokio::test]
+async fn a_missing_winner_ata_is_created_before_the_payout() {
+    let mut env = setup().await;
+    let game = Keypair::new();
+    let game_key = game.pubkey();
+    env.start_game(&game, create_game(env.player(1))).await;
+    env.play_all(game_key, &[(0, 0), (1, 0), (0, 1), (1, 1), (0, 2)])
+        .await;
+
+    // the winner empties and closes their token account before the payout
+    let ata = env.token_accounts[0];
+    assert_eq!(ata, get_associated_token_address(&env.player(0), &env.mint));
+    let balance = env.token_balance(ata).await;
+    let player = env.players[0].insecure_clone();
+    let instructions = [
+        spl_token::instruction::transfer(
+            &spl_token::ID,
+            &ata,
+            &env.token_accounts[1],
+            &env.player(0),
+            &[],
+            balance,
+        )
+        .unwrap(),
+        spl_token::instruction::close_account(
+            &spl_token::ID,
+            &ata,
+            &env.player(0),
+            &env.player(0),
+            &[],
+        )
+        .unwrap(),
+    ];
+    env.send(&instructions, &[&player]).await.unwrap();
+    assert!(env.account(ata).await.is_none());
+
+    let close = Instruction::CloseGame {
+        keep_trophy: false,
+        create_ata_if_missing: true,
+        rent_recipient: None,
+    };
+    let mut close = env.instruction(&close, &env.keys(game_key, 0));
+    close.accounts.extend([
+        AccountMeta::new_readonly(env.mint, false),
+        AccountMeta::new_readonly(env.player(0), false),
+        AccountMeta::new_readonly(spl_associated_token_account::ID, false),
+    ]);
+    let logs = env.send_with_logs(&[close], &[&player]).await.unwrap();
+
+    // the ata program is invoked first, then the token program for the payout
+    let ata_program = format!("Program {} invoke [2]", spl_associated_token_account::ID);
+    let created = logs.iter().position(|log| *log == ata_program).unwrap();
+    let token_program = format!("Program {} invoke [2]", spl_token::ID);
+    let paid = logs.iter().rposition(|log| *log == token_program).unwrap();
+    assert!(created < paid);
+    assert_eq!(env.token_balance(ata).await, 2 * STAKE);
+}